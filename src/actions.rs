@@ -1,73 +1,85 @@
 use bevy::{prelude::*, input::mouse::MouseMotion};
 use heron::prelude::*;
 use leafwing_input_manager::{Actionlike, prelude::ActionState};
+use serde::{Deserialize, Serialize};
 
-const SPEED: f32 = 8.;
-const ACCELERATION: f32 = 2.;
+use crate::{camera::{CameraRig, LookSettings}, controls::{Controls, GamepadSticks}, movement::{CharacterController, Crouch, Jump, MovementModifiers}};
 
 // Systems
 pub fn process_actions(
     mut windows: ResMut<Windows>,
     time: Res<Time>,
+    gravity: Res<Gravity>,
+    controls: Res<Controls>,
+    look_settings: Res<LookSettings>,
+    sticks: Res<GamepadSticks>,
 
     mut motion_evr: EventReader<MouseMotion>,
 
-    mut camera_query: Query<(&mut Transform), (With<Camera>, With<Parent>)>,
-    mut query: Query<(&Children, &ActionState<Action>, &mut Velocity, &mut Transform), Without<Camera>>
+    mut query: Query<(&ActionState<Action>, &mut CharacterController, &mut CameraRig, &mut Jump, Option<&Crouch>, Option<&MovementModifiers>)>
 ) {
-    let sensitivity_mult = 0.005;
     let window = windows.get_primary_mut().unwrap();
 
-    for (cameras, action_state, mut velocity, mut transform) in query.iter_mut() {
-        if window.cursor_locked() && window.is_focused() {
-            for ev in motion_evr.iter() {
-                transform.rotate(Quat::from_rotation_y(-ev.delta.x * sensitivity_mult));
-                for camera in cameras.iter() {
-                    if let Ok(mut camera_transform) = camera_query.get_mut(*camera) {
-                        camera_transform.rotate(Quat::from_rotation_x(-ev.delta.y * sensitivity_mult));
-                        
-                        //camera_transform.rotation.x - 
-                        let lock_val = camera_transform.rotation.x.clamp(-0.523599, 0.523599) - camera_transform.rotation.x;
-                        camera_transform.rotate(Quat::from_rotation_x(lock_val));
-                    }
-                }
-            }
+    // Always read every event, even when they're ignored, so they don't pile up and all land at once on refocus.
+    let mouse_movement = motion_evr.iter().fold(Vec2::ZERO, |total, ev| total + ev.delta);
+    let mouse_look = window.cursor_locked() && window.is_focused();
+
+    for (action_state, mut controller, mut rig, mut jump, crouch, modifiers) in query.iter_mut() {
+        if mouse_look {
+            rig.turn_mouse(mouse_movement, time.delta_seconds(), &look_settings);
+        } else if rig.mouse_velocity != Vec2::ZERO {
+            rig.mouse_velocity = Vec2::ZERO;
+        }
+
+        // The right stick turns at a fixed speed at full tilt, so it scales with frame time unlike the mouse.
+        let look = sticks.right * controls.gamepad.look_speed.to_radians() * time.delta_seconds();
+        if look != Vec2::ZERO {
+            rig.turn(-look.x, look.y, &look_settings);
         }
 
+        // Movement follows this frame's yaw, not the transform's, which isn't updated until the rig is applied.
+        let facing = Quat::from_rotation_y(rig.yaw);
+        let forward = facing * -Vec3::Z;
+        let right = facing * Vec3::X;
+
         
+        jump.tick(time.delta_seconds());
         if action_state.just_pressed(Action::Jump) {
-            velocity.linear += Vec3::new(0., 5., 0.); 
+            jump.press();
+        }
+        if jump.try_jump() {
+            controller.velocity.y = jump.velocity(Vec3::from(*gravity).y);
         }
 
         let mut direction = Vec3::default();
         if action_state.pressed(Action::WalkForward) {
-            direction += -transform.local_z();
+            direction += forward;
         }
         else if action_state.pressed(Action::WalkBackward) {
-            direction += transform.local_z();
+            direction -= forward;
         }
 
         if action_state.pressed(Action::StrafeLeft) {
-            direction += -transform.local_x();
+            direction -= right;
         }
         else if action_state.pressed(Action::StrafeRight) {
-            direction += transform.local_x();
+            direction += right;
         }
         
-        if direction != Vec3::default() {
-            let mut velocity_add = ((direction.normalize_or_zero()*SPEED).lerp(velocity.linear, 0.0) - velocity.linear) * time.delta_seconds();
-            velocity_add.y = 0.;
-            velocity.linear += velocity_add;
+        let speed = controller.max_speed
+            * crouch.map_or(1.0, |crouch| crouch.speed_multiplier())
+            * modifiers.map_or(1.0, |modifiers| modifiers.multiplier());
+        // The left stick keeps its magnitude so it can walk slowly, keys always go full speed.
+        controller.wish = if sticks.left != Vec2::ZERO {
+            (forward * sticks.left.y + right * sticks.left.x) * speed
         } else {
-            let mut velocity_add = (velocity.linear.lerp(Vec3::default(), 1.0) - velocity.linear) * time.delta_seconds();
-            velocity_add.y = 0.;
-            velocity.linear += velocity_add;
-        }
+            direction.normalize_or_zero() * speed
+        };
     }
 }
 
 // Data
-#[derive(Hash, PartialEq, Eq, Clone, Actionlike)]
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize, Actionlike)]
 pub enum Action {
     WalkForward,
     WalkBackward,
@@ -75,6 +87,8 @@ pub enum Action {
     StrafeRight,
     Jump,
     Crouch,
+    Sprint,
+    Dig,
     //LookUp,
     //LookDown,
     //LookLeft,
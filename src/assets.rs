@@ -1,10 +1,22 @@
 use bevy::render::mesh::{VertexAttributeValues::*, Indices, PrimitiveTopology};
-use bevy::{prelude::*, render::{mesh::VertexAttributeValues, render_resource::AddressMode}};
+use bevy::{prelude::*, asset::LoadState, render::render_resource::{AddressMode, Extent3d, TextureDimension, TextureFormat}};
 use iyes_loopless::state::NextState;
 
-use crate::{map::Tile, GameState};
+use crate::{map::{Tile, TileMaterial}, GameState};
 
-const TILING_SCALE: f32 = 1.0;
+// How many times a texture repeats per world unit.
+const TILING_SCALE: f32 = 0.5;
+
+// Stand ins for textures that failed to load. Magenta so they're easy to spot, and a flat normal.
+const PLACEHOLDER_COLOR: [u8; 4] = [255, 0, 255, 255];
+const PLACEHOLDER_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+// How steep the bumps in a normal map made from a texture's brightness are.
+const BRIGHTNESS_NORMAL_STRENGTH: f32 = 2.0;
+
+const LOADING_BACKGROUND_COLOR: Color = Color::rgb(0.02, 0.02, 0.03);
+const LOADING_BAR_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const LOADING_FILL_COLOR: Color = Color::rgb(0.8, 0.8, 0.8);
 
 //Plugin
 #[derive(Default)]
@@ -13,147 +25,421 @@ impl Plugin for AssetPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_resource::<TextureAssets>()
+            .init_resource::<FontAssets>()
+            .init_resource::<LoadingAssets>()
+            .init_resource::<LoadingProgress>()
             .init_resource::<TileAssets>()
             .init_resource::<MaterialAssets>()
             .init_resource::<MeshAssets>();
     }
 }
 
+// Kicks off loading for every file asset and records the handles so we can track them.
+pub fn load_assets (
+    asset_server: Res<AssetServer>,
+    mut texture_assets: ResMut<TextureAssets>,
+    mut font_assets: ResMut<FontAssets>,
+    mut loading_assets: ResMut<LoadingAssets>,
+) {
+    info!("Loading assets");
+
+    let mut load = |path: &str| -> Handle<Image> {
+        let handle = asset_server.load(path);
+        loading_assets.push(handle.clone_untyped());
+        handle
+    };
+
+    texture_assets.grass = load("textures/grass.png");
+    texture_assets.grass_normal = load("textures/grass_normal.png");
+    texture_assets.gray_medium_brick = load("textures/gray_medium_brick.png");
+    texture_assets.concrete = load("textures/concrete.png");
+    texture_assets.concrete_normal = load("textures/concrete_normal.png");
+    texture_assets.carpet_red = load("textures/carpet-red.png");
+    texture_assets.carpet_red_normal = load("textures/carpet-red_normal.png");
+    texture_assets.wood_planks = load("textures/wood_planks.png");
+    texture_assets.wood_planks_normal = load("textures/wood_planks_normal.png");
+
+    font_assets.ui = asset_server.load("fonts/DejaVuSansMono.ttf");
+    loading_assets.push(font_assets.ui.clone_untyped());
+}
+
+pub fn track_loading (
+    asset_server: Res<AssetServer>,
+    loading_assets: Res<LoadingAssets>,
+    mut progress: ResMut<LoadingProgress>,
+) {
+    let mut loaded = 0;
+    let mut failed = Vec::new();
+
+    for handle in loading_assets.iter() {
+        match asset_server.get_load_state(handle) {
+            LoadState::Loaded => loaded += 1,
+            LoadState::Failed => {
+                let path = asset_server.get_handle_path(handle)
+                    .map(|path| path.path().display().to_string())
+                    .unwrap_or_else(|| format!("{:?}", handle.id));
+                failed.push(path);
+            },
+            _ => (),
+        }
+    }
+
+    if loaded != progress.loaded || failed.len() != progress.failed.len() || progress.total != loading_assets.len() {
+        progress.loaded = loaded;
+        progress.total = loading_assets.len();
+
+        debug!("Loaded {}/{} assets, {} failed", progress.loaded, progress.total, failed.len());
+
+        for path in failed.iter().filter(|path| !progress.failed.contains(path)) {
+            error!("Failed to load asset: {}", path);
+        }
+        progress.failed = failed;
+    }
+}
+
+// A bar rather than text, since the font is one of the assets still loading.
+pub fn spawn_loading_screen (
+    mut commands: Commands,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: LOADING_BACKGROUND_COLOR.into(),
+            ..default()
+        })
+        .insert(LoadingScreen)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style { size: Size::new(Val::Percent(40.0), Val::Px(16.0)), ..default() },
+                    color: LOADING_BAR_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar
+                        .spawn_bundle(NodeBundle {
+                            style: Style { size: Size::new(Val::Percent(0.0), Val::Percent(100.0)), ..default() },
+                            color: LOADING_FILL_COLOR.into(),
+                            ..default()
+                        })
+                        .insert(LoadingBar);
+                });
+        });
+}
+
+pub fn update_loading_bar (
+    progress: Res<LoadingProgress>,
+
+    mut bar_query: Query<&mut Style, With<LoadingBar>>,
+) {
+    if !progress.is_changed() {
+        return;
+    }
+
+    for mut style in bar_query.iter_mut() {
+        style.size.width = Val::Percent(progress.fraction() * 100.0);
+    }
+}
+
+pub fn despawn_loading_screen (
+    screen_query: Query<Entity, With<LoadingScreen>>,
+
+    mut commands: Commands,
+) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Run condition for systems that need every tracked asset to be ready.
+pub fn assets_loaded (
+    progress: Res<LoadingProgress>,
+) -> bool {
+    progress.is_finished()
+}
+
 // For assets that we cannot load/create otherwise.
 // Programatic shit basically I guess.
 pub fn create_assets (
     mut commands: Commands,
 
-    asset_server: Res<AssetServer>,
     mut textures: ResMut<Assets<Image>>,
     mut texture_assets: ResMut<TextureAssets>,
 
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    texture_assets.grass = asset_server.load("textures/grass.png");
-    texture_assets.gray_medium_brick = asset_server.load("textures/gray_medium_brick.png");
-    texture_assets.concrete = asset_server.load("textures/concrete.png");
-
-    if let Some(gray_medium_brick_tex) = textures.get_mut(texture_assets.gray_medium_brick.clone()) {
-        gray_medium_brick_tex.sampler_descriptor.address_mode_u = AddressMode::Repeat;
-        gray_medium_brick_tex.sampler_descriptor.address_mode_v = AddressMode::Repeat;
-        gray_medium_brick_tex.sampler_descriptor.address_mode_w = AddressMode::Repeat;
+    info!("Creating assets");
+
+    // Missing textures shouldn't stop the game, they just look wrong.
+    let texture_assets = &mut *texture_assets;
+    for handle in [&mut texture_assets.grass, &mut texture_assets.gray_medium_brick, &mut texture_assets.concrete, &mut texture_assets.carpet_red, &mut texture_assets.wood_planks] {
+        replace_if_missing(&mut textures, handle, PLACEHOLDER_COLOR);
     }
-    else {
-        return;
+
+    // The brick texture has no normal map of its own, so one is made from its brightness.
+    if let Some(normal) = textures.get(&texture_assets.gray_medium_brick).and_then(|image| normal_map_from_brightness(image, BRIGHTNESS_NORMAL_STRENGTH)) {
+        texture_assets.gray_medium_brick_normal = textures.add(normal);
     }
 
-    if let Some(concrete_tex) = textures.get_mut(texture_assets.concrete.clone()) {
-        concrete_tex.sampler_descriptor.address_mode_u = AddressMode::Repeat;
-        concrete_tex.sampler_descriptor.address_mode_v = AddressMode::Repeat;
-        concrete_tex.sampler_descriptor.address_mode_w = AddressMode::Repeat;
+    for handle in [&mut texture_assets.grass_normal, &mut texture_assets.gray_medium_brick_normal, &mut texture_assets.concrete_normal, &mut texture_assets.carpet_red_normal, &mut texture_assets.wood_planks_normal] {
+        replace_if_missing(&mut textures, handle, PLACEHOLDER_NORMAL);
     }
-    else {
-        return;
+
+    // Tile uvs are in world space, so every tile texture has to repeat.
+    for handle in [&texture_assets.grass, &texture_assets.gray_medium_brick, &texture_assets.concrete, &texture_assets.carpet_red, &texture_assets.wood_planks] {
+        if let Some(texture) = textures.get_mut(handle) {
+            set_repeating(texture);
+        }
     }
 
-    let mut plane_mesh = Mesh::from(shape::Plane{size: 1.0});
-    if let Some(VertexAttributeValues::Float32x2((uvs))) = 
-       plane_mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
-
-        //for uv in uvs {
-        //    println!("uv: {:?}", uv);
-        //    uv[0] *= TILING_SCALE;
-        //    uv[1] *= TILING_SCALE;
-        //}
+    // Normal maps hold vectors, not colors, so they must not be read as sRGB.
+    for handle in [&texture_assets.grass_normal, &texture_assets.gray_medium_brick_normal, &texture_assets.concrete_normal, &texture_assets.carpet_red_normal, &texture_assets.wood_planks_normal] {
+        if let Some(texture) = textures.get_mut(handle) {
+            set_repeating(texture);
+            set_linear(texture);
+        }
     }
 
+    let mut plane_mesh = Mesh::from(shape::Plane{size: 1.0});
     let mut slab_mesh = box_no_squish(-0.5, 0.5, 0.0, 0.1, -0.5, 0.5);
 
-    /*
-    let mut slab_mesh = Mesh::from(shape::Box {
-        min_x: -0.5,
-        max_x: 0.5,
-        min_y: 0.0,
-        max_y: 0.1,
-        min_z: -0.5,
-        max_z: 0.5,
-    });
-
-    let temp_clone = slab_mesh.clone();
-    let position_values = temp_clone.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
-    
-    if let Some(Float32x2(uvs)) = slab_mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
-        if let Float32x3(positions) = position_values {
-            //iterate over every face
-            for i in (0..uvs.len()).step_by(4) {
-                println!("epic positions of a face:");
-                
-                println!("uv: {:?}. position: {:?}", uvs[i], positions[i]);
-                println!("uv: {:?}. position: {:?}", uvs[i+1], positions[i+1]);
-                println!("uv: {:?}. position: {:?}", uvs[i+2], positions[i+2]);
-                println!("uv: {:?}. position: {:?}", uvs[i+3], positions[i+3]);
-            }
+    // Normal mapped materials need tangents on every mesh they're drawn with.
+    generate_tangents(&mut plane_mesh).unwrap();
+    generate_tangents(&mut slab_mesh).unwrap();
 
-
-            /*
-            for (i, uv) in uvs.iter_mut().enumerate() {
-                println!("uv: {:?}. position: {:?}", uv, positions[i]);
-                uv[0] *= TILING_SCALE;
-                uv[1] *= TILING_SCALE * positions[i][0];
-            }
-             */
-        }
-     
-
-    }
-    */
+    let mut cube_mesh = Mesh::from(shape::Cube{size: 1.0});
+    generate_tangents(&mut cube_mesh).unwrap();
 
     let plane = meshes.add(plane_mesh);
+    let cube = meshes.add(cube_mesh);
 
     let slab = meshes.add(slab_mesh);
 
     commands.insert_resource( MeshAssets {
-        plane: plane.clone()
+        plane: plane.clone(),
+        cube: cube.clone(),
     });
 
-    let grass_material = materials.add(StandardMaterial {
-        base_color_texture: Some(texture_assets.grass.clone()),
-        perceptual_roughness: 1.0,
-        metallic: 0.,
-        reflectance: 0.,
-        ..Default::default()
-    });
-
-    let brick_material = materials.add(StandardMaterial {
-        base_color_texture: Some(texture_assets.gray_medium_brick.clone()),
-        perceptual_roughness: 1.0,
-        metallic: 0.,
-        reflectance: 0.,
-        ..Default::default()
-    });
-
-    let concrete_material = materials.add(StandardMaterial {
-        base_color_texture: Some(texture_assets.concrete.clone()),
-        perceptual_roughness: 1.0,
-        metallic: 0.,
-        reflectance: 0.,
-        ..Default::default()
-    });
+    let grass_material = materials.add(PbrTextures {
+        base_color: texture_assets.grass.clone(),
+        normal: Some(texture_assets.grass_normal.clone()),
+        ..default()
+    }.material());
+
+    let brick_material = materials.add(PbrTextures {
+        base_color: texture_assets.gray_medium_brick.clone(),
+        normal: Some(texture_assets.gray_medium_brick_normal.clone()),
+        ..default()
+    }.material());
+
+    let concrete_material = materials.add(PbrTextures {
+        base_color: texture_assets.concrete.clone(),
+        normal: Some(texture_assets.concrete_normal.clone()),
+        roughness: 0.9,
+        ..default()
+    }.material());
+
+    let carpet_red_material = materials.add(PbrTextures {
+        base_color: texture_assets.carpet_red.clone(),
+        normal: Some(texture_assets.carpet_red_normal.clone()),
+        ..default()
+    }.material());
+
+    let wood_planks_material = materials.add(PbrTextures {
+        base_color: texture_assets.wood_planks.clone(),
+        normal: Some(texture_assets.wood_planks_normal.clone()),
+        roughness: 0.6,
+        reflectance: 0.3,
+        ..default()
+    }.material());
 
     commands.insert_resource( MaterialAssets {
         grass: grass_material.clone(),
         gray_medium_brick: brick_material.clone(),
         concrete: concrete_material.clone(),
+        carpet_red: carpet_red_material.clone(),
+        wood_planks: wood_planks_material.clone(),
     });
 
     commands.insert_resource( TileAssets {
         grass: Tile {mesh: plane.clone(), material: grass_material.clone()},
         gray_medium_brick: Tile {mesh: slab.clone(), material: brick_material.clone()},
         concrete: Tile {mesh: plane.clone(), material: concrete_material.clone()},
+        carpet_red: Tile {mesh: plane.clone(), material: carpet_red_material.clone()},
+        wood_planks: Tile {mesh: plane.clone(), material: wood_planks_material.clone()},
     });
 
-    commands.insert_resource(NextState(GameState::StartMapGen));
+    commands.insert_resource(NextState(GameState::MainMenu));
 }
 
 
 // Helper Functions
+pub fn set_repeating (image: &mut Image) {
+    image.sampler_descriptor.address_mode_u = AddressMode::Repeat;
+    image.sampler_descriptor.address_mode_v = AddressMode::Repeat;
+    image.sampler_descriptor.address_mode_w = AddressMode::Repeat;
+}
+
+pub fn set_linear (image: &mut Image) {
+    if image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb {
+        image.texture_descriptor.format = TextureFormat::Rgba8Unorm;
+    }
+}
+
+// A tangent space normal map that treats brighter parts of a texture as higher.
+// Edges wrap around, since tile textures repeat. Only 8 bit rgba textures can be read.
+pub fn normal_map_from_brightness (image: &Image, strength: f32) -> Option<Image> {
+    if image.texture_descriptor.format != TextureFormat::Rgba8UnormSrgb && image.texture_descriptor.format != TextureFormat::Rgba8Unorm {
+        return None;
+    }
+
+    let size = image.texture_descriptor.size;
+    let (width, height) = (size.width as i32, size.height as i32);
+    let brightness = |x: i32, y: i32| {
+        let index = (y.rem_euclid(height) * width + x.rem_euclid(width)) as usize * 4;
+        image.data[index..index + 3].iter().map(|channel| *channel as f32).sum::<f32>() / (3.0 * 255.0)
+    };
+
+    let mut data = Vec::with_capacity(image.data.len());
+    for y in 0..height {
+        for x in 0..width {
+            // Image rows run down the texture, and the normal's y points up it.
+            let slope_x = brightness(x + 1, y) - brightness(x - 1, y);
+            let slope_y = brightness(x, y + 1) - brightness(x, y - 1);
+            let normal = Vec3::new(-slope_x * strength, slope_y * strength, 1.0).normalize();
+
+            let encoded = (normal * 0.5 + Vec3::splat(0.5)) * 255.0;
+            data.extend_from_slice(&[encoded.x as u8, encoded.y as u8, encoded.z as u8, 255]);
+        }
+    }
+
+    Some(Image::new(size, TextureDimension::D2, data, TextureFormat::Rgba8Unorm))
+}
+
+// Points the handle at a single pixel of color if whatever it was loading never arrived.
+pub fn replace_if_missing (textures: &mut Assets<Image>, handle: &mut Handle<Image>, color: [u8; 4]) {
+    if textures.contains(&*handle) {
+        return;
+    }
+
+    *handle = textures.add(Image::new_fill(
+        Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &color,
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+}
+
+// Per-vertex tangents for normal mapping, derived from positions, normals and uvs.
+// The w component stores the handedness of the bitangent.
+pub fn generate_tangents (mesh: &mut Mesh) -> Result<(), String> {
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(Float32x3(positions)) => positions.clone(),
+        _ => return Err("Mesh has no Float32x3 positions!".to_string()),
+    };
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+        Some(Float32x3(normals)) => normals.clone(),
+        _ => return Err("Mesh has no Float32x3 normals!".to_string()),
+    };
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(Float32x2(uvs)) => uvs.clone(),
+        _ => return Err("Mesh has no Float32x2 uvs!".to_string()),
+    };
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.iter().map(|i| *i as usize).collect(),
+        Some(Indices::U32(indices)) => indices.iter().map(|i| *i as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+
+    let mut tangents = vec![Vec3::ZERO; positions.len()];
+    let mut bitangents = vec![Vec3::ZERO; positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let (i0, i1, i2) = (triangle[0], triangle[1], triangle[2]);
+
+        let edge1 = Vec3::from(positions[i1]) - Vec3::from(positions[i0]);
+        let edge2 = Vec3::from(positions[i2]) - Vec3::from(positions[i0]);
+        let delta_uv1 = Vec2::from(uvs[i1]) - Vec2::from(uvs[i0]);
+        let delta_uv2 = Vec2::from(uvs[i2]) - Vec2::from(uvs[i0]);
+
+        let determinant = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / determinant;
+
+        let tangent = (edge1 * delta_uv2.y - edge2 * delta_uv1.y) * r;
+        let bitangent = (edge2 * delta_uv1.x - edge1 * delta_uv2.x) * r;
+
+        for i in triangle {
+            tangents[*i] += tangent;
+            bitangents[*i] += bitangent;
+        }
+    }
+
+    let tangents = tangents.iter().zip(bitangents.iter()).zip(normals.iter())
+        .map(|((tangent, bitangent), normal)| {
+            let normal = Vec3::from(*normal);
+            // Gram-Schmidt so the tangent is perpendicular to the normal.
+            let mut t = (*tangent - normal * normal.dot(*tangent)).normalize_or_zero();
+            if t == Vec3::ZERO {
+                t = normal.any_orthonormal_vector();
+            }
+            let w = if normal.cross(t).dot(*bitangent) < 0.0 { -1.0 } else { 1.0 };
+            [t.x, t.y, t.z, w]
+        })
+        .collect::<Vec<[f32; 4]>>();
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+    Ok(())
+}
+
+// Replaces a mesh's uvs with ones projected from world space along each face's dominant axis.
+// Faces that touch then line up no matter how the mesh was scaled or how thick it is.
+// Tangents depend on uvs, so they are regenerated if the mesh had them.
+pub fn world_space_uvs (mesh: &mut Mesh, transform: &Transform) {
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(Float32x3(positions)) => positions.clone(),
+        _ => return,
+    };
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+        Some(Float32x3(normals)) => normals.clone(),
+        _ => return,
+    };
+
+    let uvs = positions.iter().zip(normals.iter())
+        .map(|(position, normal)| {
+            let position = transform.mul_vec3(Vec3::from(*position)) * TILING_SCALE;
+            let normal = transform.rotation * Vec3::from(*normal);
+            let abs = normal.abs();
+
+            // v runs down the texture, so it follows -y on walls to keep them upright.
+            if abs.x >= abs.y && abs.x >= abs.z {
+                [-position.z * normal.x.signum(), -position.y]
+            }
+            else if abs.y >= abs.z {
+                [position.x, position.z * normal.y.signum()]
+            }
+            else {
+                [position.x * normal.z.signum(), -position.y]
+            }
+        })
+        .collect::<Vec<[f32; 2]>>();
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+
+    if mesh.attribute(Mesh::ATTRIBUTE_TANGENT).is_some() {
+        generate_tangents(mesh).unwrap();
+    }
+}
+
 pub fn box_no_squish (min_x: f32, max_x: f32, min_y: f32, max_y: f32, min_z: f32, max_z: f32) -> Mesh {
     let vertices = &[
         // Top
@@ -215,28 +501,59 @@ pub fn box_no_squish (min_x: f32, max_x: f32, min_y: f32, max_y: f32, min_z: f32
     mesh
 }
 
-// Do we even need this???
-pub fn get_locked_axis(positions: Vec<[f32; 3]>, index: usize, vertices: usize) -> Result<usize, String> {
-    'axis_iter: for (axis, _) in positions[0].iter().enumerate() {
-        let mut last_val = positions[index];
-        'value_check: for i in index..index+vertices {
-            if last_val[axis] != positions[i][axis] {
-                continue 'axis_iter;
-            }
-            last_val = positions[index]
+// Components
+#[derive(Component)]
+pub struct LoadingScreen;
+
+// The part of the loading bar that fills up.
+#[derive(Component)]
+pub struct LoadingBar;
+
+// Resources
+// Handles that have to finish loading before we leave GameState::Loading.
+#[derive(Default, Deref, DerefMut)]
+pub struct LoadingAssets (Vec<HandleUntyped>);
+
+#[derive(Default, Debug, Clone)]
+pub struct LoadingProgress {
+    pub loaded: usize,
+    pub total: usize,
+    pub failed: Vec<String>,
+}
+impl LoadingProgress {
+    // How much of the loading is done, from 0 to 1. Failed assets are done too, see is_finished.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            (self.loaded + self.failed.len()) as f32 / self.total as f32
         }
-        return Ok(axis);
     }
 
-    Err("No locked axis!".to_string())
+    // Failed assets count as finished. create_assets stands in placeholders for them.
+    pub fn is_finished(&self) -> bool {
+        self.total > 0 && self.loaded + self.failed.len() == self.total
+    }
 }
 
-// Resources
 #[derive(Default)]
 pub struct TextureAssets {
     pub grass: Handle<Image>,
+    pub grass_normal: Handle<Image>,
     pub gray_medium_brick: Handle<Image>,
+    // Made from gray_medium_brick in create_assets rather than loaded.
+    pub gray_medium_brick_normal: Handle<Image>,
     pub concrete: Handle<Image>,
+    pub concrete_normal: Handle<Image>,
+    pub carpet_red: Handle<Image>,
+    pub carpet_red_normal: Handle<Image>,
+    pub wood_planks: Handle<Image>,
+    pub wood_planks_normal: Handle<Image>,
+}
+
+#[derive(Default)]
+pub struct FontAssets {
+    pub ui: Handle<Font>,
 }
 
 #[derive(Default)]
@@ -244,11 +561,14 @@ pub struct MaterialAssets {
     pub grass: Handle<StandardMaterial>,
     pub gray_medium_brick: Handle<StandardMaterial>,
     pub concrete: Handle<StandardMaterial>,
+    pub carpet_red: Handle<StandardMaterial>,
+    pub wood_planks: Handle<StandardMaterial>,
 }
 
 #[derive(Default)]
 pub struct MeshAssets {
     pub plane: Handle<Mesh>,
+    pub cube: Handle<Mesh>,
 }
 
 #[derive(Default)]
@@ -256,4 +576,51 @@ pub struct TileAssets {
     pub grass: Tile,
     pub gray_medium_brick: Tile,
     pub concrete: Tile,
+    pub carpet_red: Tile,
+    pub wood_planks: Tile,
+}
+impl TileAssets {
+    pub fn get(&self, material: TileMaterial) -> &Tile {
+        match material {
+            TileMaterial::Grass => &self.grass,
+            TileMaterial::GrayMediumBrick => &self.gray_medium_brick,
+            TileMaterial::Concrete => &self.concrete,
+            TileMaterial::CarpetRed => &self.carpet_red,
+            TileMaterial::WoodPlanks => &self.wood_planks,
+        }
+    }
+}
+
+// Data
+// The set of maps a tile material can be built from.
+#[derive(Clone)]
+pub struct PbrTextures {
+    pub base_color: Handle<Image>,
+    pub normal: Option<Handle<Image>>,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub reflectance: f32,
+}
+impl PbrTextures {
+    pub fn material(&self) -> StandardMaterial {
+        StandardMaterial {
+            base_color_texture: Some(self.base_color.clone()),
+            normal_map_texture: self.normal.clone(),
+            perceptual_roughness: self.roughness,
+            metallic: self.metallic,
+            reflectance: self.reflectance,
+            ..default()
+        }
+    }
+}
+impl Default for PbrTextures {
+    fn default() -> Self {
+        PbrTextures {
+            base_color: Handle::default(),
+            normal: None,
+            roughness: 1.0,
+            metallic: 0.0,
+            reflectance: 0.0,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,304 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+use bevy::render::{camera::PerspectiveProjection, render_resource::{Extent3d, TextureDimension, TextureFormat}};
+
+use crate::{controls::MetaAction, map::{Explored, GridMap, MapScale, PathExit, RegionMap, TileChanged, TileFlags, TileType}, player::Player};
+
+// The minimap shows this many cells in every direction around the player.
+const MINIMAP_RADIUS: i32 = 16;
+const MINIMAP_CELL_PIXELS: i32 = 6;
+const AUTOMAP_CELL_PIXELS: i32 = 8;
+// Turning only redraws the maps once the facing line has moved into another of this many directions.
+const FACING_STEPS: f32 = 32.0;
+
+const BACKGROUND_COLOR: [u8; 4] = [0, 0, 0, 160];
+const ROOM_COLOR: [u8; 4] = [90, 90, 100, 255];
+const CORRIDOR_COLOR: [u8; 4] = [110, 85, 60, 255];
+const LIQUID_COLOR: [u8; 4] = [40, 70, 160, 255];
+const STAIRS_COLOR: [u8; 4] = [220, 200, 60, 255];
+const WALL_COLOR: [u8; 4] = [230, 230, 230, 255];
+const DOOR_COLOR: [u8; 4] = [230, 130, 30, 255];
+const PLAYER_COLOR: [u8; 4] = [220, 40, 40, 255];
+
+//Plugin
+#[derive(Default)]
+pub struct AutomapPlugin;
+impl Plugin for AutomapPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Automap>();
+    }
+}
+
+// Systems
+// Creates the images both maps are drawn into and the UI nodes that show them.
+pub fn spawn_automap (
+    map: Res<GridMap>,
+    mut automap: ResMut<Automap>,
+    mut images: ResMut<Assets<Image>>,
+
+    mut commands: Commands,
+) {
+    let minimap_size = (MINIMAP_RADIUS * 2 + 1) * MINIMAP_CELL_PIXELS;
+    automap.minimap = images.add(blank_image(minimap_size, minimap_size));
+    // The full map covers the map as it was generated. Anything dug out past that is only on the minimap.
+    automap.full_min = IVec2::new(map.min().x, map.min().z);
+    automap.full_cells = IVec2::new(map.width(), map.length()).max(IVec2::ONE);
+    automap.full = images.add(blank_image(automap.full_cells.x * AUTOMAP_CELL_PIXELS, automap.full_cells.y * AUTOMAP_CELL_PIXELS));
+
+    commands
+        .spawn_bundle(ImageBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect { top: Val::Px(10.0), right: Val::Px(10.0), ..default() },
+                size: Size::new(Val::Px(minimap_size as f32), Val::Px(minimap_size as f32)),
+                ..default()
+            },
+            image: automap.minimap.clone().into(),
+            ..default()
+        })
+        .insert(Minimap);
+
+    commands
+        .spawn_bundle(ImageBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect { top: Val::Percent(5.0), left: Val::Percent(5.0), ..default() },
+                size: Size::new(Val::Percent(90.0), Val::Percent(90.0)),
+                ..default()
+            },
+            image: automap.full.clone().into(),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(FullAutomap);
+}
+
+// Opens and closes the full map, and browses floors while it's open.
+pub fn automap_input (
+    map: Res<GridMap>,
+    mut automap: ResMut<Automap>,
+    map_scale: Res<MapScale>,
+
+    meta_query: Query<&ActionState<MetaAction>>,
+    player_query: Query<&Transform, With<Player>>,
+) {
+    let action_state = match meta_query.iter().next() {
+        Some(action_state) => action_state,
+        None => return,
+    };
+
+    if action_state.just_pressed(MetaAction::ToggleAutomap) {
+        automap.open = !automap.open;
+        automap.layer = None;
+    }
+
+    if !automap.open {
+        return;
+    }
+
+    let step = action_state.just_pressed(MetaAction::MapLayerUp) as i32 - action_state.just_pressed(MetaAction::MapLayerDown) as i32;
+    if step == 0 {
+        return;
+    }
+
+    let current = automap.layer.or_else(|| {
+        player_query.iter().next().map(|transform| map_scale.world_to_cell(transform.translation).y)
+    });
+
+    if let Some(current) = current {
+        automap.layer = Some((current + step).clamp(map.min().y, map.max().y.max(map.min().y)));
+    }
+}
+
+// Redraws the minimap around the player, and the full map when it's open.
+// Every redraw uploads the whole image again, so it only happens when something drawn has changed.
+pub fn draw_automap (
+    map: Res<GridMap>,
+    map_scale: Res<MapScale>,
+    explored: Res<Explored>,
+    regions: Res<RegionMap>,
+    automap: Res<Automap>,
+    mut images: ResMut<Assets<Image>>,
+    mut last_view: Local<Option<(IVec3, i32)>>,
+
+    mut ev_tile_changed: EventReader<TileChanged>,
+
+    player_query: Query<&Transform, With<Player>>,
+    camera_query: Query<&GlobalTransform, With<PerspectiveProjection>>,
+    exit_query: Query<Entity, With<PathExit>>,
+    mut full_query: Query<&mut Visibility, With<FullAutomap>>,
+) {
+    for mut visibility in full_query.iter_mut() {
+        if visibility.is_visible != automap.open {
+            visibility.is_visible = automap.open;
+        }
+    }
+
+    let player = match player_query.iter().next() {
+        Some(transform) => map_scale.world_to_cell(transform.translation),
+        None => return,
+    };
+
+    let facing = camera_query.iter().next()
+        .map(|transform| transform.rotation * -Vec3::Z)
+        .map(|forward| Vec2::new(forward.x, forward.z).normalize_or_zero())
+        .unwrap_or(Vec2::ZERO);
+
+    let facing_step = (facing.y.atan2(facing.x) / std::f32::consts::TAU * FACING_STEPS).round() as i32;
+    let view_changed = *last_view != Some((player, facing_step));
+    let map_changed = ev_tile_changed.iter().count() > 0 || explored.is_changed() || regions.is_changed();
+
+    if !view_changed && !map_changed && !automap.is_changed() {
+        return;
+    }
+    *last_view = Some((player, facing_step));
+
+    let layers = MapLayers {
+        map: &map,
+        explored: &explored,
+        regions: &regions,
+        corridors: exit_query.iter().collect(),
+        player,
+        facing,
+    };
+
+    if let Some(image) = images.get_mut(&automap.minimap) {
+        let min = IVec3::new(player.x - MINIMAP_RADIUS, player.y, player.z - MINIMAP_RADIUS);
+        layers.draw(image, min, IVec2::splat(MINIMAP_RADIUS * 2 + 1), MINIMAP_CELL_PIXELS);
+    }
+
+    if automap.open {
+        if let Some(image) = images.get_mut(&automap.full) {
+            let layer = automap.layer.unwrap_or(player.y);
+            layers.draw(image, IVec3::new(automap.full_min.x, layer, automap.full_min.y), automap.full_cells, AUTOMAP_CELL_PIXELS);
+        }
+    }
+}
+
+// Helper Functions
+fn blank_image (width: i32, height: i32) -> Image {
+    Image::new_fill(
+        Extent3d { width: width as u32, height: height as u32, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &BACKGROUND_COLOR,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn fill (image: &mut Image, x: i32, y: i32, w: i32, h: i32, color: [u8; 4]) {
+    let width = image.texture_descriptor.size.width as i32;
+    let height = image.texture_descriptor.size.height as i32;
+
+    for py in y.max(0)..(y + h).min(height) {
+        for px in x.max(0)..(x + w).min(width) {
+            let index = ((py * width + px) * 4) as usize;
+            image.data[index..index + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+// Components
+#[derive(Component)]
+pub struct Minimap;
+
+#[derive(Component)]
+pub struct FullAutomap;
+
+// Resources
+#[derive(Default)]
+pub struct Automap {
+    pub open: bool,
+    // The floor being browsed on the full map. None follows the player.
+    pub layer: Option<i32>,
+    pub minimap: Handle<Image>,
+    pub full: Handle<Image>,
+    // The first cell and number of cells along x and z the full map shows.
+    pub full_min: IVec2,
+    pub full_cells: IVec2,
+}
+
+// Data
+// Everything needed to draw a top down view of one floor. North is up.
+struct MapLayers<'a> {
+    map: &'a GridMap,
+    explored: &'a Explored,
+    regions: &'a RegionMap,
+    corridors: HashSet<Entity>,
+    player: IVec3,
+    facing: Vec2,
+}
+impl<'a> MapLayers<'a> {
+    // Draws cells.x by cells.y cells from min along x and z into the image.
+    // Only explored cells are drawn.
+    fn draw(&self, image: &mut Image, min: IVec3, cells: IVec2, cell_pixels: i32) {
+        let image_width = image.texture_descriptor.size.width as i32;
+        let image_height = image.texture_descriptor.size.height as i32;
+        fill(image, 0, 0, image_width, image_height, BACKGROUND_COLOR);
+
+        for i in 0..cells.x {
+            for j in 0..cells.y {
+                let position = IVec3::new(min.x + i, min.y, min.z + j);
+                if !self.explored.contains(position) {
+                    continue;
+                }
+
+                let x = i * cell_pixels;
+                let y = (cells.y - 1 - j) * cell_pixels;
+
+                let floor_color = if self.map.tile_has(position, TileType::Center, TileFlags::STAIRS) {
+                    Some(STAIRS_COLOR)
+                } else if self.map.tile_has(position, TileType::Floor, TileFlags::LIQUID) {
+                    Some(LIQUID_COLOR)
+                } else {
+                    match self.regions.get(&position) {
+                        Some(region) if self.corridors.contains(region) => Some(CORRIDOR_COLOR),
+                        Some(_) => Some(ROOM_COLOR),
+                        None if self.map.position_occupied(position) => Some(ROOM_COLOR),
+                        None => None,
+                    }
+                };
+
+                if let Some(color) = floor_color {
+                    fill(image, x, y, cell_pixels, cell_pixels, color);
+                }
+
+                let edge = (cell_pixels / 4).max(1);
+                for side in [TileType::North, TileType::East, TileType::South, TileType::West] {
+                    let data = match self.map[position][side] {
+                        Some(data) => data,
+                        None => continue,
+                    };
+
+                    let color = if data.flags.contains(TileFlags::DOOR) { DOOR_COLOR } else { WALL_COLOR };
+                    match side {
+                        TileType::North => fill(image, x, y, cell_pixels, edge, color),
+                        TileType::South => fill(image, x, y + cell_pixels - edge, cell_pixels, edge, color),
+                        TileType::East => fill(image, x + cell_pixels - edge, y, edge, cell_pixels, color),
+                        _ => fill(image, x, y, edge, cell_pixels, color),
+                    }
+                }
+            }
+        }
+
+        if self.player.y != min.y {
+            return;
+        }
+
+        // A dot for the player with a line showing which way they're facing.
+        let center = Vec2::new(
+            (self.player.x - min.x) as f32 + 0.5,
+            (cells.y - 1 - (self.player.z - min.z)) as f32 + 0.5,
+        ) * cell_pixels as f32;
+
+        let dot = (cell_pixels / 2).max(2);
+        fill(image, center.x as i32 - dot / 2, center.y as i32 - dot / 2, dot, dot, PLAYER_COLOR);
+
+        for step in 0..cell_pixels * 2 {
+            let point = center + Vec2::new(self.facing.x, -self.facing.y) * step as f32;
+            fill(image, point.x as i32, point.y as i32, 1, 1, PLAYER_COLOR);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use bevy::prelude::*;
+use bevy::render::camera::CameraProjection;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{load_config, save_config};
+
+// Where look settings are read from and saved to, relative to the working directory.
+const SETTINGS_PATH: &str = "settings.ron";
+
+//Plugin
+#[derive(Default)]
+pub struct CameraRigPlugin;
+impl Plugin for CameraRigPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(LookSettings::load());
+    }
+}
+
+// Systems
+// Points actors and their cameras where their rigs say. Yaw turns the actor, pitch tilts only the camera.
+pub fn apply_camera_rigs (
+    mut rig_query: Query<(&CameraRig, &Children, &mut Transform), (Changed<CameraRig>, Without<Camera>)>,
+    mut camera_query: Query<&mut Transform, With<Camera>>,
+) {
+    for (rig, children, mut transform) in rig_query.iter_mut() {
+        transform.rotation = Quat::from_rotation_y(rig.yaw);
+
+        for child in children.iter() {
+            if let Ok(mut camera_transform) = camera_query.get_mut(*child) {
+                camera_transform.rotation = Quat::from_rotation_x(rig.pitch);
+            }
+        }
+    }
+}
+
+pub fn apply_field_of_view (
+    windows: Res<Windows>,
+    settings: Res<LookSettings>,
+
+    mut camera_query: Query<(&mut PerspectiveProjection, &mut Camera)>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for (mut projection, mut camera) in camera_query.iter_mut() {
+        projection.fov = settings.fov.to_radians();
+
+        // The projection matrix is only rebuilt on resize, so it has to be updated by hand.
+        if let Some(window) = windows.get_primary() {
+            projection.update(window.width(), window.height());
+        }
+        camera.projection_matrix = projection.get_projection_matrix();
+    }
+}
+
+// Components
+// Where an actor is looking, in radians. Yaw is around the up axis and pitch is up (positive) and down.
+#[derive(Component, Default, Debug, Clone, Copy)]
+pub struct CameraRig {
+    pub yaw: f32,
+    pub pitch: f32,
+    // Smoothed mouse movement, in counts per second.
+    pub mouse_velocity: Vec2,
+}
+impl CameraRig {
+    // Turns by a frame's worth of mouse movement, taken over delta seconds.
+    // Smoothing and acceleration both work from the mouse's speed rather than the raw per-frame
+    // movement, so turning feels the same whatever the frame rate.
+    pub fn turn_mouse(&mut self, movement: Vec2, delta: f32, settings: &LookSettings) {
+        if delta <= 0.0 {
+            return;
+        }
+
+        let velocity = movement / delta;
+        // Smoothing eases the mouse's speed toward the real one. The turn is that eased speed integrated
+        // over the frame rather than sampled once, so splitting the same movement into more frames changes nothing.
+        let movement = if settings.smoothing > 0.0 {
+            let decay = (-delta / settings.smoothing).exp();
+            let lag = self.mouse_velocity - velocity;
+            self.mouse_velocity = velocity + lag * decay;
+            velocity * delta + lag * settings.smoothing * (1.0 - decay)
+        } else {
+            self.mouse_velocity = velocity;
+            movement
+        };
+
+        let gain = 1.0 + settings.acceleration * self.mouse_velocity.length() / 1000.0;
+        let turn = movement * gain * settings.sensitivity.to_radians();
+        self.turn(-turn.x, -turn.y, settings);
+    }
+
+    // Turns by the given angles in radians, keeping pitch within the settings' limits.
+    pub fn turn(&mut self, yaw: f32, pitch: f32, settings: &LookSettings) {
+        let pitch = if settings.invert_y { -pitch } else { pitch };
+
+        self.yaw = (self.yaw + yaw) % std::f32::consts::TAU;
+        self.pitch = (self.pitch + pitch).clamp(settings.min_pitch.to_radians(), settings.max_pitch.to_radians());
+    }
+}
+
+// Resources
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LookSettings {
+    // Degrees turned per unit of mouse movement.
+    pub sensitivity: f32,
+    pub invert_y: bool,
+    // How far the camera can look down and up, in degrees.
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    // Vertical field of view, in degrees.
+    pub fov: f32,
+    // How long mouse movement takes to catch up when smoothed, in seconds. 0 turns smoothing off.
+    pub smoothing: f32,
+    // Extra turn for faster mouse movement. Sensitivity grows by this fraction per 1000 counts per second.
+    pub acceleration: f32,
+}
+impl LookSettings {
+    pub fn load() -> LookSettings {
+        load_config(SETTINGS_PATH)
+    }
+
+    pub fn save(&self) {
+        save_config(SETTINGS_PATH, self);
+    }
+}
+impl Default for LookSettings {
+    fn default() -> Self {
+        LookSettings {
+            sensitivity: 0.3,
+            invert_y: false,
+            min_pitch: -85.0,
+            max_pitch: 85.0,
+            fov: 70.0,
+            smoothing: 0.0,
+            acceleration: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turn_clamps_pitch() {
+        let settings = LookSettings::default();
+        let mut rig = CameraRig::default();
+
+        rig.turn(0.0, 10.0, &settings);
+        assert_eq!(rig.pitch, settings.max_pitch.to_radians());
+
+        rig.turn(0.0, -20.0, &settings);
+        assert_eq!(rig.pitch, settings.min_pitch.to_radians());
+    }
+
+    #[test]
+    fn turn_inverts_pitch() {
+        let settings = LookSettings { invert_y: true, ..default() };
+        let mut rig = CameraRig::default();
+
+        rig.turn(0.5, 0.25, &settings);
+        assert_eq!(rig.yaw, 0.5);
+        assert_eq!(rig.pitch, -0.25);
+    }
+    #[test]
+    fn turn_mouse_is_frame_rate_independent() {
+        let settings = LookSettings::default();
+        let movement = Vec2::new(40.0, 0.0);
+        let delta = 1.0 / 30.0;
+
+        let mut once = CameraRig::default();
+        once.turn_mouse(movement, delta, &settings);
+
+        let mut split = CameraRig::default();
+        for _ in 0..4 {
+            split.turn_mouse(movement / 4.0, delta / 4.0, &settings);
+        }
+
+        assert!((once.yaw - split.yaw).abs() < 1e-5, "{} != {}", once.yaw, split.yaw);
+    }
+
+    #[test]
+    fn smoothed_turn_mouse_is_frame_rate_independent() {
+        let settings = LookSettings { smoothing: 0.1, ..default() };
+        // The same steady mouse movement for half a second, in counts per second.
+        let speed = Vec2::new(600.0, -300.0);
+        let turn_at = |frames: usize| {
+            let delta = 0.5 / frames as f32;
+            let mut rig = CameraRig::default();
+            for _ in 0..frames {
+                rig.turn_mouse(speed * delta, delta, &settings);
+            }
+            rig
+        };
+
+        // 30 and 144 frames per second.
+        let slow = turn_at(15);
+        let fast = turn_at(72);
+
+        assert!((slow.yaw - fast.yaw).abs() < 1e-4, "{} != {}", slow.yaw, fast.yaw);
+        assert!((slow.pitch - fast.pitch).abs() < 1e-4, "{} != {}", slow.pitch, fast.pitch);
+    }
+}
@@ -0,0 +1,35 @@
+use std::fs;
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+// Helper Functions
+// Reads a config file from the working directory, falling back to the defaults if it's missing or broken.
+// A missing file is written out with the defaults so there's something to edit, a broken one is left alone.
+pub fn load_config<T: DeserializeOwned + Serialize + Default> (path: &str) -> T {
+    match fs::read_to_string(path) {
+        Ok(contents) => match ron::from_str(&contents) {
+            Ok(config) => config,
+            Err(error) => {
+                error!("Couldn't parse {}, using defaults: {}", path, error);
+                T::default()
+            }
+        },
+        Err(_) => {
+            info!("No {} found, writing defaults", path);
+            let config = T::default();
+            save_config(path, &config);
+            config
+        }
+    }
+}
+
+pub fn save_config<T: Serialize> (path: &str, config: &T) {
+    let result = ron::ser::to_string_pretty(config, default())
+        .map_err(|error| error.to_string())
+        .and_then(|contents| fs::write(path, contents).map_err(|error| error.to_string()));
+
+    if let Err(error) = result {
+        error!("Couldn't save {}: {}", path, error);
+    }
+}
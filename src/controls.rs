@@ -0,0 +1,562 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use leafwing_input_manager::{Actionlike, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{actions::Action, assets::FontAssets, config::{load_config, save_config}};
+
+// Where bindings are read from and saved to, relative to the working directory.
+const CONTROLS_PATH: &str = "controls.ron";
+
+const PANEL_COLOR: Color = Color::rgba(0.05, 0.05, 0.05, 0.85);
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const BUTTON_HOVER_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+const TEXT_COLOR: Color = Color::WHITE;
+const FONT_SIZE: f32 = 18.0;
+
+//Plugin
+#[derive(Default)]
+pub struct ControlsPlugin;
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugin(InputManagerPlugin::<MetaAction>::default())
+            .insert_resource(Controls::load())
+            .init_resource::<Rebinding>()
+            .init_resource::<ActiveGamepad>()
+            .init_resource::<GamepadSticks>();
+    }
+}
+
+// Systems
+// Meta actions work outside of any actor, so they get an entity of their own.
+pub fn spawn_meta_controls (
+    controls: Res<Controls>,
+
+    mut commands: Commands,
+) {
+    commands
+        .spawn_bundle(InputManagerBundle::<MetaAction> {
+            action_state: ActionState::default(),
+            input_map: controls.meta_input_map(),
+        })
+        .insert(MetaControls);
+}
+
+// Rebuilds every input map from the bindings whenever they or the active gamepad change.
+pub fn apply_controls (
+    controls: Res<Controls>,
+    active_gamepad: Res<ActiveGamepad>,
+
+    mut action_query: Query<&mut InputMap<Action>>,
+    mut meta_query: Query<&mut InputMap<MetaAction>>,
+) {
+    if !controls.is_changed() && !active_gamepad.is_changed() {
+        return;
+    }
+
+    for mut input_map in action_query.iter_mut() {
+        *input_map = controls.input_map();
+        if let Some(gamepad) = **active_gamepad {
+            input_map.set_gamepad(gamepad);
+        }
+    }
+    for mut input_map in meta_query.iter_mut() {
+        *input_map = controls.meta_input_map();
+        if let Some(gamepad) = **active_gamepad {
+            input_map.set_gamepad(gamepad);
+        }
+    }
+}
+
+// Uses the first gamepad connected, moving on to another when it's disconnected.
+pub fn track_gamepads (
+    gamepads: Res<Gamepads>,
+    mut active_gamepad: ResMut<ActiveGamepad>,
+    mut ev_gamepad: EventReader<GamepadEvent>,
+) {
+    for GamepadEvent(gamepad, event_type) in ev_gamepad.iter() {
+        match event_type {
+            GamepadEventType::Connected if active_gamepad.is_none() => {
+                info!("Using gamepad {:?}", gamepad);
+                **active_gamepad = Some(*gamepad);
+            }
+            GamepadEventType::Disconnected if **active_gamepad == Some(*gamepad) => {
+                **active_gamepad = gamepads.iter().find(|other| *other != gamepad).copied();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Reads both sticks of the active gamepad, with the dead zone applied.
+pub fn read_sticks (
+    controls: Res<Controls>,
+    active_gamepad: Res<ActiveGamepad>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut sticks: ResMut<GamepadSticks>,
+) {
+    let gamepad = match **active_gamepad {
+        Some(gamepad) => gamepad,
+        None => {
+            *sticks = GamepadSticks::default();
+            return;
+        }
+    };
+
+    let stick = |x: GamepadAxisType, y: GamepadAxisType| {
+        let raw = Vec2::new(
+            axes.get(GamepadAxis(gamepad, x)).unwrap_or(0.0),
+            axes.get(GamepadAxis(gamepad, y)).unwrap_or(0.0),
+        );
+        controls.gamepad.apply_dead_zone(raw)
+    };
+
+    sticks.left = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+    sticks.right = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+}
+
+// Opens and closes the rebinding screen. The cursor is freed while it's open so the buttons can be clicked.
+pub fn toggle_rebinding (
+    mut windows: ResMut<Windows>,
+    controls: Res<Controls>,
+    fonts: Res<FontAssets>,
+    mut rebinding: ResMut<Rebinding>,
+
+    meta_query: Query<&ActionState<MetaAction>>,
+
+    mut commands: Commands,
+) {
+    if !meta_query.iter().any(|action_state| action_state.just_pressed(MetaAction::Rebind)) || rebinding.waiting.is_some() {
+        return;
+    }
+
+    if let Some(root) = rebinding.root.take() {
+        commands.entity(root).despawn_recursive();
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(false);
+        window.set_cursor_visibility(true);
+    }
+
+    let text_style = TextStyle { font: fonts.ui.clone(), font_size: FONT_SIZE, color: TEXT_COLOR };
+
+    let root = commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect { top: Val::Percent(10.0), left: Val::Percent(30.0), ..default() },
+                size: Size::new(Val::Percent(40.0), Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: Rect::all(Val::Px(10.0)),
+                ..default()
+            },
+            color: PANEL_COLOR.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section("Controls (click to rebind or unbind)", text_style.clone(), default()),
+                ..default()
+            });
+
+            let targets = Action::variants().map(BindTarget::Action)
+                .chain(MetaAction::variants().map(BindTarget::Meta));
+
+            for target in targets {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            margin: Rect::all(Val::Px(2.0)),
+                            padding: Rect::all(Val::Px(4.0)),
+                            ..default()
+                        },
+                        color: BUTTON_COLOR.into(),
+                        ..default()
+                    })
+                    .insert(RebindButton(target.clone()))
+                    .with_children(|button| {
+                        button
+                            .spawn_bundle(TextBundle {
+                                text: Text::with_section(controls.describe(&target), text_style.clone(), default()),
+                                ..default()
+                            })
+                            .insert(RebindLabel(target.clone()));
+                    });
+            }
+        })
+        .id();
+
+    rebinding.root = Some(root);
+}
+
+// Clicking a row starts waiting for the next key or mouse button to bind to it.
+// Clicks while already waiting are left for capture_binding, so rows can be bound to the mouse.
+pub fn rebind_buttons (
+    mut rebinding: ResMut<Rebinding>,
+
+    mut button_query: Query<(&Interaction, &RebindButton, &mut UiColor), Changed<Interaction>>,
+) {
+    for (interaction, button, mut color) in button_query.iter_mut() {
+        match interaction {
+            Interaction::Clicked if rebinding.waiting.is_none() => {
+                rebinding.waiting = Some(button.0.clone());
+                rebinding.just_started = true;
+            }
+            Interaction::Clicked => {}
+            Interaction::Hovered => *color = BUTTON_HOVER_COLOR.into(),
+            Interaction::None => *color = BUTTON_COLOR.into(),
+        }
+    }
+}
+
+// Binds the next key, mouse button or gamepad button pressed to whatever is waiting for one, and saves the result.
+// Escape cancels instead of binding. Delete unbinds the keyboard and mouse side, Backspace the gamepad side.
+pub fn capture_binding (
+    key: Res<Input<KeyCode>>,
+    btn: Res<Input<MouseButton>>,
+    pad: Res<Input<GamepadButton>>,
+    mut controls: ResMut<Controls>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    let target = match rebinding.waiting.clone() {
+        Some(target) => target,
+        None => return,
+    };
+
+    // The click that picked the row is still just pressed this frame, and would bind itself.
+    if rebinding.just_started {
+        rebinding.just_started = false;
+        return;
+    }
+
+    if key.just_pressed(KeyCode::Escape) {
+        rebinding.waiting = None;
+        return;
+    }
+
+    let unbind_gamepad = if key.just_pressed(KeyCode::Delete) {
+        Some(false)
+    } else if key.just_pressed(KeyCode::Back) {
+        Some(true)
+    } else {
+        None
+    };
+    if let Some(gamepad) = unbind_gamepad {
+        controls.unbind(&target, gamepad);
+        controls.save();
+        rebinding.waiting = None;
+        return;
+    }
+
+    let binding = if let Some(key) = key.get_just_pressed().next() {
+        Binding::Key(*key)
+    } else if let Some(button) = btn.get_just_pressed().next() {
+        Binding::Mouse(*button)
+    } else if let Some(GamepadButton(_, button)) = pad.get_just_pressed().next() {
+        Binding::Gamepad(*button)
+    } else {
+        return;
+    };
+
+    controls.rebind(target, binding);
+    controls.save();
+    rebinding.waiting = None;
+}
+
+pub fn update_rebind_labels (
+    controls: Res<Controls>,
+    rebinding: Res<Rebinding>,
+
+    mut label_query: Query<(&RebindLabel, &mut Text)>,
+) {
+    if !controls.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+
+    for (label, mut text) in label_query.iter_mut() {
+        text.sections[0].value = if rebinding.waiting.as_ref() == Some(&label.0) {
+            format!("{}: press a key, mouse or pad button... (Delete unbinds keys, Backspace unbinds pad)", label.0)
+        } else {
+            controls.describe(&label.0)
+        };
+    }
+}
+
+// Components
+#[derive(Component)]
+pub struct MetaControls;
+
+#[derive(Component)]
+pub struct RebindButton (BindTarget);
+
+#[derive(Component)]
+pub struct RebindLabel (BindTarget);
+
+// Resources
+// Every binding, for both actors and meta actions. Loaded from CONTROLS_PATH.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Controls {
+    pub actions: Vec<(Action, Binding)>,
+    pub meta: Vec<(MetaAction, Binding)>,
+    #[serde(default)]
+    pub gamepad: StickSettings,
+}
+impl Default for Controls {
+    fn default() -> Self {
+        Controls {
+            actions: vec![
+                (Action::WalkForward, Binding::Key(KeyCode::W)),
+                (Action::WalkBackward, Binding::Key(KeyCode::S)),
+                (Action::StrafeLeft, Binding::Key(KeyCode::A)),
+                (Action::StrafeRight, Binding::Key(KeyCode::D)),
+                (Action::Jump, Binding::Key(KeyCode::Space)),
+                (Action::Crouch, Binding::Key(KeyCode::LControl)),
+                (Action::Sprint, Binding::Key(KeyCode::LShift)),
+                (Action::Dig, Binding::Mouse(MouseButton::Left)),
+                (Action::Jump, Binding::Gamepad(GamepadButtonType::South)),
+                (Action::Crouch, Binding::Gamepad(GamepadButtonType::East)),
+                (Action::Sprint, Binding::Gamepad(GamepadButtonType::LeftThumb)),
+                (Action::Dig, Binding::Gamepad(GamepadButtonType::RightTrigger2)),
+            ],
+            meta: vec![
+                (MetaAction::LockCursor, Binding::Mouse(MouseButton::Left)),
+                (MetaAction::UnlockCursor, Binding::Key(KeyCode::Tab)),
+                (MetaAction::Pause, Binding::Key(KeyCode::Escape)),
+                (MetaAction::ToggleAutomap, Binding::Key(KeyCode::M)),
+                (MetaAction::MapLayerUp, Binding::Key(KeyCode::PageUp)),
+                (MetaAction::MapLayerDown, Binding::Key(KeyCode::PageDown)),
+                (MetaAction::Rebind, Binding::Key(KeyCode::F1)),
+                (MetaAction::ToggleAutomap, Binding::Gamepad(GamepadButtonType::Select)),
+                (MetaAction::MapLayerUp, Binding::Gamepad(GamepadButtonType::DPadUp)),
+                (MetaAction::MapLayerDown, Binding::Gamepad(GamepadButtonType::DPadDown)),
+                (MetaAction::Pause, Binding::Gamepad(GamepadButtonType::Start)),
+            ],
+            gamepad: StickSettings::default(),
+        }
+    }
+}
+impl Controls {
+    pub fn load() -> Controls {
+        load_config(CONTROLS_PATH)
+    }
+
+    pub fn save(&self) {
+        save_config(CONTROLS_PATH, self);
+    }
+
+    pub fn input_map(&self) -> InputMap<Action> {
+        build_input_map(&self.actions)
+    }
+
+    pub fn meta_input_map(&self) -> InputMap<MetaAction> {
+        build_input_map(&self.meta)
+    }
+
+    // Replaces the target's binding on the same device as the new one. Its other bindings are kept,
+    // so rebinding a key doesn't take the action off the gamepad.
+    pub fn rebind(&mut self, target: BindTarget, binding: Binding) {
+        match target {
+            BindTarget::Action(action) => {
+                self.actions.retain(|(bound, old)| *bound != action || !old.same_device(&binding));
+                self.actions.push((action, binding));
+            }
+            BindTarget::Meta(action) => {
+                self.meta.retain(|(bound, old)| *bound != action || !old.same_device(&binding));
+                self.meta.push((action, binding));
+            }
+        }
+    }
+
+    // Removes the target's bindings on one side, either the gamepad or the keyboard and mouse.
+    pub fn unbind(&mut self, target: &BindTarget, gamepad: bool) {
+        match target {
+            BindTarget::Action(action) => self.actions.retain(|(bound, old)| bound != action || old.is_gamepad() != gamepad),
+            BindTarget::Meta(action) => self.meta.retain(|(bound, old)| bound != action || old.is_gamepad() != gamepad),
+        }
+    }
+
+    // Lists keyboard and mouse bindings, then gamepad ones, so it's clear which side a rebind will replace.
+    pub fn describe(&self, target: &BindTarget) -> String {
+        let bindings = match target {
+            BindTarget::Action(action) => self.actions.iter().filter(|(bound, _)| bound == action).map(|(_, binding)| *binding).collect::<Vec<Binding>>(),
+            BindTarget::Meta(action) => self.meta.iter().filter(|(bound, _)| bound == action).map(|(_, binding)| *binding).collect::<Vec<Binding>>(),
+        };
+
+        let (pad, other): (Vec<Binding>, Vec<Binding>) = bindings.into_iter().partition(|binding| binding.is_gamepad());
+        let join = |bindings: Vec<Binding>, unbound: &str| {
+            if bindings.is_empty() {
+                unbound.to_string()
+            } else {
+                bindings.iter().map(|binding| binding.to_string()).collect::<Vec<String>>().join(", ")
+            }
+        };
+
+        format!("{}: {} | {}", target, join(other, "unbound"), join(pad, "Pad unbound"))
+    }
+}
+
+// The gamepad being read from. Only one is used at a time.
+#[derive(Default, Deref, DerefMut)]
+pub struct ActiveGamepad (Option<Gamepad>);
+
+// Stick positions this frame, after the dead zone. Each is at most 1 long.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct GamepadSticks {
+    pub left: Vec2,
+    pub right: Vec2,
+}
+
+// The rebinding screen, if it's open, and what's waiting for a new binding.
+#[derive(Default)]
+pub struct Rebinding {
+    pub root: Option<Entity>,
+    pub waiting: Option<BindTarget>,
+    // Set on the frame waiting starts, so that frame's input is skipped.
+    pub just_started: bool,
+}
+impl Rebinding {
+    pub fn open(&self) -> bool {
+        self.root.is_some()
+    }
+}
+
+// Helper Functions
+fn build_input_map<A: Actionlike> (bindings: &[(A, Binding)]) -> InputMap<A> {
+    let mut input_map = InputMap::default();
+    for (action, binding) in bindings {
+        match binding {
+            Binding::Key(key) => input_map.insert(action.clone(), *key),
+            Binding::Mouse(button) => input_map.insert(action.clone(), *button),
+            Binding::Gamepad(button) => input_map.insert(action.clone(), *button),
+        };
+    }
+    input_map
+}
+
+// Data
+// Actions that control the game itself rather than an actor.
+#[derive(Actionlike, Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum MetaAction {
+    LockCursor,
+    UnlockCursor,
+    #[serde(alias = "Exit")]
+    Pause,
+    ToggleAutomap,
+    MapLayerUp,
+    MapLayerDown,
+    Rebind,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+impl Binding {
+    // Keyboard and mouse count as one device and the gamepad as another, matching the two sides describe shows.
+    pub fn same_device(&self, other: &Binding) -> bool {
+        self.is_gamepad() == other.is_gamepad()
+    }
+
+    pub fn is_gamepad(&self) -> bool {
+        matches!(self, Binding::Gamepad(_))
+    }
+}
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{:?}", key),
+            Binding::Mouse(button) => write!(f, "Mouse {:?}", button),
+            Binding::Gamepad(button) => write!(f, "Pad {:?}", button),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BindTarget {
+    Action(Action),
+    Meta(MetaAction),
+}
+impl fmt::Display for BindTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindTarget::Action(action) => write!(f, "{:?}", action),
+            BindTarget::Meta(action) => write!(f, "{:?}", action),
+        }
+    }
+}
+
+// How the gamepad's sticks feel.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct StickSettings {
+    // Stick positions closer to the center than this are ignored, to hide drift.
+    pub dead_zone: f32,
+    // How fast the right stick turns the camera at full tilt, in degrees per second.
+    pub look_speed: f32,
+}
+impl Default for StickSettings {
+    fn default() -> Self {
+        StickSettings {
+            dead_zone: 0.15,
+            look_speed: 180.0,
+        }
+    }
+}
+impl StickSettings {
+    // A radial dead zone, rescaled so the stick still covers the whole range just past it.
+    pub fn apply_dead_zone(&self, stick: Vec2) -> Vec2 {
+        let length = stick.length();
+        if length <= self.dead_zone {
+            return Vec2::ZERO;
+        }
+
+        let scaled = ((length - self.dead_zone) / (1.0 - self.dead_zone)).min(1.0);
+        stick / length * scaled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_zone_zeroes_small_input() {
+        let settings = StickSettings::default();
+        assert_eq!(settings.apply_dead_zone(Vec2::new(0.1, 0.05)), Vec2::ZERO);
+    }
+
+    #[test]
+    fn dead_zone_keeps_full_tilt_and_direction() {
+        let settings = StickSettings::default();
+        let stick = Vec2::new(0.6, 0.8);
+        let applied = settings.apply_dead_zone(stick);
+
+        assert!((applied.length() - 1.0).abs() < 1e-5);
+        assert!((applied.normalize() - stick.normalize()).length() < 1e-5);
+    }
+    #[test]
+    fn rebinding_replaces_keyboard_and_mouse_together() {
+        let mut controls = Controls::default();
+        let dig = BindTarget::Action(Action::Dig);
+        controls.rebind(dig.clone(), Binding::Key(KeyCode::E));
+
+        assert_eq!(controls.describe(&dig), "Dig: E | Pad RightTrigger2");
+    }
+
+    #[test]
+    fn unbinding_clears_one_side() {
+        let mut controls = Controls::default();
+        let dig = BindTarget::Action(Action::Dig);
+
+        controls.unbind(&dig, false);
+        assert_eq!(controls.describe(&dig), "Dig: unbound | Pad RightTrigger2");
+
+        controls.unbind(&dig, true);
+        assert_eq!(controls.describe(&dig), "Dig: unbound | Pad unbound");
+    }
+}
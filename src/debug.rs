@@ -0,0 +1,166 @@
+use bevy::{prelude::*, input::mouse::MouseWheel};
+use heron::prelude::*;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{actions::Action, camera::CameraRig, movement::{move_characters, CharacterController, Crouch, Stamina}, player::Player, GameState};
+
+// Debug tools aren't player controls, so they sit on fixed keys instead of going through the bindings.
+const NOCLIP_KEY: KeyCode = KeyCode::F3;
+
+const NOCLIP_SPEED: f32 = 8.0;
+const MIN_NOCLIP_SPEED: f32 = 0.5;
+const MAX_NOCLIP_SPEED: f32 = 128.0;
+const NOCLIP_SPRINT_MULTIPLIER: f32 = 4.0;
+
+// Plugin
+// Tools for poking around generated maps. Only built into debug builds.
+#[derive(Default)]
+pub struct DebugPlugin;
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(toggle_noclip)
+                    .with_system(fly_noclip.into_conditional().after(toggle_noclip).after(move_characters))
+                    .into()
+            );
+    }
+}
+
+// Systems
+// Turning noclip off puts the player back where they took off from.
+// Crouch and Stamina are taken off while flying, since Crouch and Sprint fly down and fast instead,
+// and put back as they were afterwards.
+pub fn toggle_noclip (
+    key: Res<Input<KeyCode>>,
+
+    mut player_query: Query<(Entity, &mut Transform, &mut CharacterController, Option<&NoClip>, Option<&Crouch>, Option<&Stamina>), With<Player>>,
+
+    mut commands: Commands,
+) {
+    if !key.just_pressed(NOCLIP_KEY) {
+        return;
+    }
+
+    for (entity, mut transform, mut controller, noclip, crouch, stamina) in player_query.iter_mut() {
+        controller.velocity = Vec3::ZERO;
+
+        match noclip {
+            Some(noclip) => {
+                info!("Noclip off");
+
+                transform.translation = noclip.start;
+
+                let mut entity_commands = commands.entity(entity);
+                entity_commands
+                    .remove::<NoClip>()
+                    .remove::<CollisionLayers>();
+                if let Some(crouch) = noclip.crouch.clone() {
+                    entity_commands.insert(crouch);
+                }
+                if let Some(stamina) = noclip.stamina.clone() {
+                    entity_commands.insert(stamina);
+                }
+            }
+            None => {
+                info!("Noclip on");
+
+                let noclip = NoClip {
+                    crouch: crouch.cloned(),
+                    stamina: stamina.cloned(),
+                    ..NoClip::new(transform.translation)
+                };
+
+                // Without layers nothing else bumps into the player while they're inside walls.
+                commands.entity(entity)
+                    .insert(noclip)
+                    .insert(CollisionLayers::none())
+                    .remove::<Crouch>()
+                    .remove::<Stamina>();
+            }
+        }
+    }
+}
+
+// Flies wherever the camera is looking, ignoring collision and gravity.
+// Runs after the character controller and overrides whatever it did.
+pub fn fly_noclip (
+    time: Res<Time>,
+
+    mut ev_wheel: EventReader<MouseWheel>,
+
+    mut player_query: Query<(&ActionState<Action>, &CameraRig, &mut NoClip, &mut Transform, &mut CharacterController)>,
+) {
+    let scroll: f32 = ev_wheel.iter().map(|ev| ev.y.signum()).sum();
+
+    for (action_state, rig, mut noclip, mut transform, mut controller) in player_query.iter_mut() {
+        // Each notch of the wheel doubles or halves the speed.
+        if scroll != 0.0 {
+            noclip.speed = (noclip.speed * 2.0_f32.powf(scroll)).clamp(MIN_NOCLIP_SPEED, MAX_NOCLIP_SPEED);
+            info!("Noclip speed {}", noclip.speed);
+        }
+
+        let yaw = Quat::from_rotation_y(rig.yaw);
+        let forward = yaw * Quat::from_rotation_x(rig.pitch) * -Vec3::Z;
+        let right = yaw * Vec3::X;
+
+        let mut direction = Vec3::ZERO;
+        if action_state.pressed(Action::WalkForward) {
+            direction += forward;
+        }
+        if action_state.pressed(Action::WalkBackward) {
+            direction -= forward;
+        }
+        if action_state.pressed(Action::StrafeRight) {
+            direction += right;
+        }
+        if action_state.pressed(Action::StrafeLeft) {
+            direction -= right;
+        }
+        if action_state.pressed(Action::Jump) {
+            direction += Vec3::Y;
+        }
+        if action_state.pressed(Action::Crouch) {
+            direction -= Vec3::Y;
+        }
+
+        let mut speed = noclip.speed;
+        if action_state.pressed(Action::Sprint) {
+            speed *= NOCLIP_SPRINT_MULTIPLIER;
+        }
+
+        noclip.position += direction.normalize_or_zero() * speed * time.delta_seconds();
+        transform.translation = noclip.position;
+
+        // Otherwise gravity keeps building up and slams the player down when noclip is turned off.
+        controller.velocity = Vec3::ZERO;
+        controller.wish = Vec3::ZERO;
+    }
+}
+
+// Components
+#[derive(Component, Debug, Clone)]
+pub struct NoClip {
+    // Where noclip was turned on, to snap back to.
+    pub start: Vec3,
+    // Kept separately from the transform so the character controller can't drag it around.
+    pub position: Vec3,
+    pub speed: f32,
+    // Taken off the player while flying, to put back afterwards.
+    pub crouch: Option<Crouch>,
+    pub stamina: Option<Stamina>,
+}
+impl NoClip {
+    pub fn new(start: Vec3) -> NoClip {
+        NoClip {
+            start,
+            position: start,
+            speed: NOCLIP_SPEED,
+            crouch: None,
+            stamina: None,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use heron::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+use rand::Rng;
+
+use crate::{actions::Action, assets::{MeshAssets, TileAssets}, controls::Rebinding, map::*, player::Player};
+
+// How far from their eyes a digger can reach, in cells.
+const DIG_REACH: f32 = 1.5;
+
+const DEBRIS_PIECES: usize = 4;
+const DEBRIS_SIZE: f32 = 0.1;
+const DEBRIS_LIFETIME: f32 = 4.0;
+
+// Plugin
+#[derive(Default)]
+pub struct DiggingPlugin;
+impl Plugin for DiggingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<DigEvent>()
+            .init_resource::<TileDamage>();
+    }
+}
+
+// Systems
+// Only digs while the cursor is grabbed. The click that grabs it is on the same button as Dig by default,
+// so Dig has to be let go once after grabbing before it swings.
+pub fn player_dig (
+    time: Res<Time>,
+    windows: Res<Windows>,
+    rebinding: Res<Rebinding>,
+    map: Res<GridMap>,
+    map_scale: Res<MapScale>,
+    mut armed: Local<bool>,
+
+    mut ev_dig: EventWriter<DigEvent>,
+
+    mut player_query: Query<(Entity, &Transform, &Children, &ActionState<Action>, &mut DiggingTool), With<Player>>,
+    camera_query: Query<&GlobalTransform, With<Camera>>,
+) {
+    let grabbed = windows.get_primary().is_some_and(|window| window.cursor_locked()) && !rebinding.open();
+
+    for (entity, transform, children, action_state, mut tool) in player_query.iter_mut() {
+        tool.cooldown.tick(time.delta());
+
+        if !grabbed {
+            *armed = false;
+            continue;
+        }
+        if !action_state.pressed(Action::Dig) {
+            *armed = true;
+            continue;
+        }
+
+        if !*armed || !tool.cooldown.finished() {
+            continue;
+        }
+
+        let (eye, forward) = match children.iter().find_map(|child| camera_query.get(*child).ok()) {
+            Some(camera_transform) => (camera_transform.translation, camera_transform.rotation * -Vec3::Z),
+            None => (transform.translation, -transform.local_z()),
+        };
+
+        if let Some(hit) = raycast(&map, &map_scale, eye, forward, DIG_REACH * map_scale.max_element()) {
+            ev_dig.send(DigEvent { digger: entity, position: hit.position, tile_type: hit.tile_type, power: tool.power });
+            tool.cooldown.reset();
+        }
+    }
+}
+
+// Damages dug tiles and breaks them once their material gives out.
+// Whatever was behind a broken tile is opened up or carved out so there's never a hole into nothing.
+pub fn apply_digs (
+    mut map: ResMut<GridMap>,
+    mut regions: ResMut<RegionMap>,
+    mut graph: ResMut<PortalGraph>,
+    mut damage: ResMut<TileDamage>,
+    map_scale: Res<MapScale>,
+    tiles: Res<TileAssets>,
+    mesh_assets: Res<MeshAssets>,
+
+    mut ev_dig: EventReader<DigEvent>,
+
+    mut commands: Commands,
+) {
+    for ev in ev_dig.iter() {
+        let data = match map[ev.position][ev.tile_type] {
+            Some(data) => data,
+            None => continue,
+        };
+
+        // The map grows to fit, so there's always room to dig into.
+        let neighbour = ev.position + ev.tile_type.direction();
+
+        let dealt = damage.entry((ev.position, ev.tile_type)).or_default();
+        *dealt += ev.power;
+        if *dealt < data.material.durability() {
+            continue;
+        }
+        damage.remove(&(ev.position, ev.tile_type));
+
+        clear_tile(&mut commands, &mut map, ev.tile_type, ev.position);
+
+        if regions.contains_key(&neighbour) || map.position_occupied(neighbour) {
+            clear_tile(&mut commands, &mut map, ev.tile_type.opposite(), neighbour);
+            damage.remove(&(neighbour, ev.tile_type.opposite()));
+
+            // Breaking into another room or corridor opens a portal between them, so culling can see through the hole.
+            if let (Some(start), Some(end)) = (regions.get(&ev.position).copied(), regions.get(&neighbour).copied()) {
+                let exists = graph.portals_of(start).any(|portal| portal.other(start) == end && portal.position == ev.position && portal.side == ev.tile_type);
+                if start != end && !exists {
+                    graph.add(Portal { regions: [start, end], position: ev.position, side: ev.tile_type });
+                }
+            }
+        }
+        else {
+            let floor = map[ev.position][TileType::Floor].unwrap_or_else(|| TileData::new(TileKind::Floor, data.material));
+            let ceiling = map[ev.position][TileType::Ceiling].unwrap_or_else(|| TileData::new(TileKind::Ceiling, data.material));
+            let walls = TileData::new(TileKind::Wall, data.material);
+
+            carve_cell(&mut map, neighbour, ev.tile_type.opposite(), walls, floor, ceiling);
+
+            if let Some(region) = regions.get(&ev.position).copied() {
+                regions.insert(neighbour, region);
+            }
+        }
+
+        let origin = tile_transform(&map_scale, ev.tile_type, ev.position.as_vec3()).translation;
+        spawn_debris(&mut commands, &mesh_assets, &tiles.get(data.material).material, &map_scale, origin);
+    }
+}
+
+pub fn despawn_debris (
+    time: Res<Time>,
+
+    mut debris_query: Query<(Entity, &mut Debris)>,
+
+    mut commands: Commands,
+) {
+    for (entity, mut debris) in debris_query.iter_mut() {
+        if debris.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Helper Functions
+// Turns a solid position into an open one, walled off on every side but the one it was dug from.
+pub fn carve_cell (map: &mut GridMap, position: IVec3, open_side: TileType, walls: TileData, floor: TileData, ceiling: TileData) {
+    for side in [TileType::North, TileType::East, TileType::South, TileType::West, TileType::Floor, TileType::Ceiling] {
+        if side == open_side {
+            continue;
+        }
+
+        let data = match side {
+            TileType::Floor => floor,
+            TileType::Ceiling => ceiling,
+            _ => walls,
+        };
+
+        place_tile(map, data, side, position);
+    }
+}
+
+pub fn spawn_debris (commands: &mut Commands, mesh_assets: &MeshAssets, material: &Handle<StandardMaterial>, scale: &MapScale, origin: Vec3) {
+    let mut rng = rand::thread_rng();
+    let size = **scale * DEBRIS_SIZE;
+
+    for _ in 0..DEBRIS_PIECES {
+        let offset = Vec3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5)) * **scale * 0.5;
+        let velocity = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(0.0..2.0), rng.gen_range(-1.0..1.0));
+
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: mesh_assets.cube.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(origin + offset).with_scale(size),
+                ..default()
+            })
+            .insert(RigidBody::Dynamic)
+            .insert(CollisionShape::Cuboid {
+                half_extends: size / 2.0,
+                border_radius: None,
+            })
+            .insert(Velocity::from_linear(velocity))
+            .insert(Debris(Timer::from_seconds(DEBRIS_LIFETIME, false)));
+    }
+}
+
+// Components
+// Anything that can dig. Both the player and monsters use this.
+#[derive(Component, Clone)]
+pub struct DiggingTool {
+    // Durability removed per hit.
+    pub power: f32,
+    pub cooldown: Timer,
+}
+impl Default for DiggingTool {
+    fn default() -> Self {
+        DiggingTool {
+            power: 1.0,
+            cooldown: Timer::from_seconds(0.4, false),
+        }
+    }
+}
+
+#[derive(Component, Deref, DerefMut)]
+pub struct Debris (Timer);
+
+// Events
+// Sent by anything that wants to hit a tile. Monsters can send these directly.
+#[derive(Debug, Clone, Copy)]
+pub struct DigEvent {
+    pub digger: Entity,
+    pub position: IVec3,
+    pub tile_type: TileType,
+    pub power: f32,
+}
+
+// Resources
+// Damage dealt to tiles that haven't broken yet.
+#[derive(Default, Deref, DerefMut)]
+pub struct TileDamage (HashMap<(IVec3, TileType), f32>);
@@ -1,6 +1,8 @@
 
 
 //#![windows_subsystem = "windows"]
+// Bevy systems take every resource and query they use as an argument, and queries spell out their filters.
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
@@ -13,6 +15,12 @@ use actions::*;
 pub mod player;
 use player::*;
 
+pub mod movement;
+use movement::*;
+
+pub mod camera;
+use camera::*;
+
 pub mod setup;
 use setup::*;
 
@@ -23,6 +31,23 @@ use map::*;
 pub mod assets;
 use assets::*;
 
+pub mod digging;
+use digging::*;
+
+pub mod automap;
+use automap::*;
+
+pub mod controls;
+use controls::*;
+
+pub mod config;
+
+pub mod menu;
+use menu::*;
+
+#[cfg(debug_assertions)]
+pub mod debug;
+
 fn main() {
     let mut app = App::new();
 
@@ -42,31 +67,99 @@ fn main() {
 
         .add_plugin(MapPlugin)
         .add_plugin(AssetPlugin)
+        .add_plugin(DiggingPlugin)
+        .add_plugin(AutomapPlugin)
+        .add_plugin(ControlsPlugin)
+        .add_plugin(CameraRigPlugin)
+        .add_plugin(MenuPlugin)
 
         .add_stage_after(CoreStage::Update, "Update Geometry", SystemStage::parallel())
+        .add_stage_after("Update Geometry", "Build Chunks", SystemStage::parallel())
 
         .add_loopless_state(GameState::Loading)
 
-        .add_system(create_assets.run_in_state(GameState::Loading))
+        .add_startup_system(load_assets)
+        .add_startup_system(spawn_meta_controls)
+        .add_startup_system(spawn_ui_camera)
+        .add_startup_system(spawn_loading_screen)
+        .add_system(track_loading.run_in_state(GameState::Loading))
+        .add_system(update_loading_bar.run_in_state(GameState::Loading).after(track_loading))
+        .add_exit_system(GameState::Loading, despawn_loading_screen)
+        .add_system(
+            create_assets
+                .run_in_state(GameState::Loading)
+                .run_if(assets_loaded)
+                .after(track_loading)
+        )
+
+        .add_enter_system(GameState::MainMenu, spawn_main_menu)
+        .add_enter_system(GameState::MainMenu, freeze_game)
+        .add_exit_system(GameState::MainMenu, despawn_menus)
+        .add_system(seed_input.run_in_state(GameState::MainMenu))
 
+        .add_enter_system(GameState::Paused, spawn_pause_menu)
+        .add_enter_system(GameState::Paused, freeze_game)
+        .add_exit_system(GameState::Paused, despawn_menus)
+
+        .add_enter_system(GameState::Settings, spawn_settings_menu)
+        .add_exit_system(GameState::Settings, despawn_menus)
+        .add_exit_system(GameState::Settings, save_settings)
+        .add_system(update_setting_labels.run_in_state(GameState::Settings))
+
+        .add_system(menu_buttons.run_not_in_state(GameState::Playing))
+        .add_system(toggle_pause)
+
+        .add_enter_system(GameState::Playing, resume_game)
+
+        .add_enter_system(GameState::StartMapGen, clear_map)
+        .add_enter_system(GameState::StartMapGen, despawn_game)
         //.add_enter_system(GameState::StartMapGen, map_branching_start)
         .add_system(map_branching_start.run_in_state(GameState::StartMapGen))
 
         .add_system(map_branching_generation.run_in_state(GameState::MapGen))
 
         .add_enter_system(GameState::SpawnActors, spawn_actors)
+        .add_enter_system(GameState::SpawnActors, build_portal_graph)
+        .add_enter_system(GameState::SpawnActors, spawn_automap)
 
 
         // TODO: Change this once asset_loader supports loopless.
+        // Ordering inside a ConditionSet goes through into_conditional, a plain .after() makes a descriptor the set can't take.
         .add_system_set(
             ConditionSet::new()
                 .run_in_state(GameState::Playing)
-                .with_system(process_actions)
+                .with_system(detect_ground)
+                .with_system(crouch)
+                .with_system(sprint.into_conditional().after(crouch))
+                .with_system(terrain_modifiers)
+                .with_system(tick_modifiers.into_conditional().after(sprint).after(terrain_modifiers))
+                .with_system(process_actions.into_conditional().after(detect_ground).after(tick_modifiers))
+                .with_system(move_characters.into_conditional().after(process_actions))
+                .with_system(apply_camera_rigs.into_conditional().after(process_actions))
+                .with_system(apply_field_of_view)
                 .with_system(meta_input)
+                .with_system(update_explored)
+                .with_system(portal_culling.into_conditional().after(update_explored))
+                .with_system(stream_chunks)
+                .with_system(player_dig)
+                .with_system(apply_digs.into_conditional().after(player_dig))
+                .with_system(despawn_debris)
+                .with_system(invalidate_paths)
+                .with_system(update_paths.into_conditional().after(invalidate_paths))
+                .with_system(follow_paths.into_conditional().after(update_paths))
+                .with_system(automap_input)
+                .with_system(toggle_rebinding)
+                .with_system(rebind_buttons)
+                .with_system(capture_binding.into_conditional().after(rebind_buttons).after(toggle_rebinding))
+                .with_system(update_rebind_labels.into_conditional().after(capture_binding).after(toggle_rebinding))
+                .with_system(draw_automap.into_conditional().after(automap_input).after(update_explored))
                 .into()
         )
 
         .add_system(check_scene_objects)
+        .add_system(track_gamepads)
+        .add_system(apply_controls.after(track_gamepads))
+        .add_system(read_sticks.after(track_gamepads).before(process_actions))
 
         .add_system_set_to_stage(
             "Update Geometry",
@@ -76,11 +169,22 @@ fn main() {
                 .with_system(spawn_entrances.after(spawn_rooms))
         )
 
+        .add_system_set_to_stage(
+            "Build Chunks",
+            SystemSet::new()
+                .with_system(send_tile_changes)
+                .with_system(rebuild_chunks.after(send_tile_changes))
+        )
+
 
         //.add_system(spawn_surface)
         //.add_system(spawn_surfaces)
+        ;
+
+    #[cfg(debug_assertions)]
+    app.add_plugin(debug::DebugPlugin);
 
-        .run();
+    app.run();
 }
 
 
@@ -88,6 +192,7 @@ fn main() {
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameState {
     Loading,
+    MainMenu, Paused, Settings,
     StartMapGen, MapGen, SpawnActors,
     Playing,
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use heron::{CollisionShape, RigidBody, CollisionLayers};
+
+use crate::assets::TileAssets;
+
+use super::{FaceRect, MapScale, TileFlags};
+
+// Planes have no thickness, so their colliders get this much (in cells) behind the visible side.
+const MIN_THICKNESS: f32 = 0.1;
+
+// Helper Functions
+// One static body per chunk, with a box child for every merged solid face.
+// The boxes are built from the same rectangles as the chunk's meshes, so they line up exactly.
+pub fn spawn_chunk_collider (commands: &mut Commands, meshes: &Assets<Mesh>, tiles: &TileAssets, scale: &MapScale, faces: &[FaceRect]) -> Option<Entity> {
+    let boxes = faces.iter()
+        .filter(|face| face.data.flags.contains(TileFlags::SOLID))
+        .filter_map(|face| {
+            let mesh = meshes.get(&tiles.get(face.data.material).mesh)?;
+            let aabb = mesh.compute_aabb()?;
+            let (transform, stretch) = face.placement(scale);
+
+            let stretch = Vec3::new(stretch.x, 1.0, stretch.y);
+            let mut min = Vec3::from(aabb.min()) * stretch;
+            let max = Vec3::from(aabb.max()) * stretch;
+
+            // Tile meshes face local +y, so thin ones are thickened downwards, away from the room.
+            if max.y - min.y < MIN_THICKNESS {
+                min.y = max.y - MIN_THICKNESS;
+            }
+
+            let center = transform.mul_vec3((min + max) / 2.0);
+            let half_extends = (max - min) / 2.0 * transform.scale;
+
+            Some((Transform::from_translation(center).with_rotation(transform.rotation), half_extends))
+        })
+        .collect::<Vec<(Transform, Vec3)>>();
+
+    if boxes.is_empty() {
+        return None;
+    }
+
+    let collider = commands
+        .spawn()
+        .insert(Transform::identity())
+        .insert(GlobalTransform::identity())
+        .insert(RigidBody::Static)
+        .with_children(|c| {
+            for (transform, half_extends) in boxes {
+                c.spawn()
+                    .insert(transform)
+                    .insert(GlobalTransform::identity())
+                    .insert(CollisionShape::Cuboid {
+                        half_extends,
+                        border_radius: None,
+                    })
+                    .insert(CollisionLayers::default());
+            }
+        })
+        .id();
+
+    Some(collider)
+}
+
+// Resources
+#[derive(Default, Deref, DerefMut)]
+pub struct ChunkColliders (HashMap<IVec3, Entity>);
@@ -0,0 +1,76 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use crate::player::Player;
+
+use super::{GridMap, MapScale, RegionMap, TileChanged, CHUNK_SIZE, chunk_of, field_of_view};
+
+// How far the player can see, in cells.
+const SIGHT_RADIUS: i32 = 12;
+
+// Systems
+// Marks everything in the player's field of view as explored.
+// Only reruns when the player moves to another position or the map changes, since nothing else can change what's visible.
+pub fn update_explored (
+    map: Res<GridMap>,
+    map_scale: Res<MapScale>,
+    regions: Res<RegionMap>,
+    mut explored: ResMut<Explored>,
+    mut ev_tile_changed: EventReader<TileChanged>,
+
+    player_query: Query<&Transform, With<Player>>,
+) {
+    let map_changed = ev_tile_changed.iter().count() > 0;
+
+    let origin = match player_query.iter().next() {
+        Some(transform) => map_scale.world_to_cell(transform.translation),
+        None => return,
+    };
+
+    if !map_changed && explored.last_origin == Some(origin) {
+        return;
+    }
+    explored.last_origin = Some(origin);
+
+    for position in field_of_view(&map, origin, SIGHT_RADIUS) {
+        explored.insert(position);
+
+        if let Some(region) = regions.get(&position) {
+            explored.regions.insert(*region);
+        }
+    }
+}
+
+// Resources
+// Every position the player has seen, one bit each, and the rooms and exits those positions belong to.
+// Bits are kept per chunk and only allocated once something in the chunk is seen, since the map has no fixed size.
+#[derive(Default, Clone)]
+pub struct Explored {
+    chunks: HashMap<IVec3, Vec<u64>>,
+    regions: HashSet<Entity>,
+    last_origin: Option<IVec3>,
+}
+impl Explored {
+    pub fn contains(&self, position: IVec3) -> bool {
+        let index = Explored::index(position);
+        self.chunks.get(&chunk_of(position))
+            .is_some_and(|bits| bits[index / 64] & (1 << (index % 64)) != 0)
+    }
+
+    pub fn insert(&mut self, position: IVec3) {
+        let index = Explored::index(position);
+        let bits = self.chunks.entry(chunk_of(position))
+            .or_insert_with(|| vec![0; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize / 64]);
+        bits[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn region_explored(&self, region: Entity) -> bool {
+        self.regions.contains(&region)
+    }
+
+    fn index(position: IVec3) -> usize {
+        let local = position - chunk_of(position) * CHUNK_SIZE;
+        (local.x + local.y * CHUNK_SIZE + local.z * CHUNK_SIZE * CHUNK_SIZE) as usize
+    }
+}
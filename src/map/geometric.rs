@@ -1,13 +1,11 @@
 use bevy::prelude::*;
-use rand::{Rng, prelude::SliceRandom};
+use rand::{prelude::SliceRandom, rngs::StdRng};
 
-use super::{WithinBoxIterator, TileType, GridMap};
+use super::{WithinBoxIterator, TileType, TileData, GridMap};
 
 
 // Helper functions
-pub fn random_surface_wall_point(exclude: Vec<IVec3>, rect: Rect3, map: &GridMap) -> Option<(IVec3, TileType)> {
-    let mut rng = rand::thread_rng();
-    
+pub fn random_surface_wall_point(exclude: Vec<IVec3>, rect: Rect3, map: &GridMap, rng: &mut StdRng) -> Option<(IVec3, TileType)> {
     let mut surface_wall_points = Vec::<IVec3>::new();
 
     let min = rect.min();
@@ -24,7 +22,7 @@ pub fn random_surface_wall_point(exclude: Vec<IVec3>, rect: Rect3, map: &GridMap
 
     surface_wall_points.retain(|point| !exclude.contains(point));
 
-    if let Some(point) = surface_wall_points.choose(&mut rng) {
+    if let Some(point) = surface_wall_points.choose(rng) {
         let map_point = map[*point];
         let mut possible_walls = Vec::new();
 
@@ -42,7 +40,7 @@ pub fn random_surface_wall_point(exclude: Vec<IVec3>, rect: Rect3, map: &GridMap
             possible_walls.push(TileType::West);
         }
 
-        if let Some(wall) = possible_walls.choose(&mut rng) {
+        if let Some(wall) = possible_walls.choose(rng) {
             Some((*point, *wall))
         }
         else {
@@ -76,9 +74,9 @@ pub struct Rect3Room {
     // It would be bad to spawn two actors in the same location.
     pub spawned_actors: Vec<Entity>, 
     pub rect: Rect3,
-    pub ceiling: Tile,
-    pub walls: Tile,
-    pub floor: Tile,
+    pub ceiling: TileData,
+    pub walls: TileData,
+    pub floor: TileData,
 }
 impl IntoIterator for Rect3Room {
     type Item = IVec3;
@@ -105,9 +103,9 @@ pub struct HoleEntrance (pub IVec3Tile);
 #[derive(Component, Debug, Default, Clone)]
 pub struct PathExit {
     pub path: Vec<IVec3Tile>,
-    pub ceiling: Tile,
-    pub walls: Tile,
-    pub floor: Tile,
+    pub ceiling: TileData,
+    pub walls: TileData,
+    pub floor: TileData,
 }
 
 // Data
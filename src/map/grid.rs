@@ -1,41 +1,54 @@
+use std::collections::{HashMap, HashSet};
 use std::ops::{Index, IndexMut};
 
 use bevy::prelude::*;
 use enum_map::{EnumMap, Enum, enum_map};
-use heron::{CollisionShape, RigidBody, CollisionLayers};
-use ndarray::{Array3, Axis};
+use ndarray::Array3;
 
-use super::{geometric::Tile, WithinBoxIterator};
+use super::{WithinBoxIterator, CHUNK_SIZE};
 
 
 // TODO: We might want/need these functions to replace already existing tiles.
 //       Or we might want to make new functions to suit that purpose.
+// These are the only things that should write to the map, since they're what records TileChanged.
 // Helper Systems
 pub fn clear_position ( commands: &mut Commands, map: &mut GridMap, position: IVec3) {
-    for (_tile, opt_entity) in map[position] {
-        if let Some(entity) = opt_entity {
-            commands.entity(entity).despawn();
+    for (tile_type, data) in map[position] {
+        if data.is_some() {
+            clear_tile(commands, map, tile_type, position);
         }
     }
-
-    map[position] = TileType::empty();
 }
 
 pub fn clear_tile ( commands: &mut Commands, map: &mut GridMap, tile_type: TileType, position: IVec3) {
-    if let Some(entity) = map[position][tile_type] {
+    if let Some(entity) = map.take_entity(position, tile_type) {
         commands.entity(entity).despawn_recursive();
     }
 
-    map[position][tile_type] = None;
+    map.set(position, tile_type, None);
 }
 
-pub fn spawn_tile ( commands: &mut Commands, map: &mut GridMap, scale: &MapScale, tile: Tile, tile_type: TileType, position: IVec3) {
-    let transformation = TileOffsets::default()[tile_type];
-    let mut transform = Transform::default();
-    let mut collision_size = Vec3::new(0.5, 0.1, 0.5);
+// Only writes the data. Rendering and collision are built per chunk from it.
+pub fn place_tile ( map: &mut GridMap, data: TileData, tile_type: TileType, position: IVec3) {
+    map.set(position, tile_type, Some(data));
+}
 
-    transform.translation = Vec3::new(position.x as f32, position.y as f32, position.z as f32) + transformation.translation;
-    transform.translation *= **scale;
+// Systems
+// Turns the changes recorded by the map into events so other systems can react to them.
+pub fn send_tile_changes (
+    mut map: ResMut<GridMap>,
+    mut ev_tile_changed: EventWriter<TileChanged>,
+) {
+    if map.has_changes() {
+        ev_tile_changed.send_batch(map.take_changes().into_iter());
+    }
+}
+
+// Helper Functions
+// Where a tile of the given type sits in the world. position is in grid space and may be fractional.
+pub fn tile_transform (scale: &MapScale, tile_type: TileType, position: Vec3) -> Transform {
+    let transformation = TileOffsets::default()[tile_type];
+    let mut transform = Transform::from_translation((position + transformation.translation) * **scale);
 
     if transformation.rotation.x != 0.0 {
         transform.rotate(Quat::from_rotation_x(transformation.rotation.x))
@@ -49,82 +62,178 @@ pub fn spawn_tile ( commands: &mut Commands, map: &mut GridMap, scale: &MapScale
 
     transform.scale = **scale;
 
-    collision_size *= **scale;
-
-    let spawned_tile = commands
-        .spawn_bundle(PbrBundle {
-            mesh: tile.mesh.clone(),
-            material: tile.material.clone(),
-            ..Default::default()    
-        })  
-        .insert(transform)
-        .insert(GlobalTransform::default())
-        .insert(CollisionShape::Cuboid {
-            half_extends: collision_size,
-            border_radius: None,
-        })
-        .insert(RigidBody::Static)
-        .insert(CollisionLayers::default())
-        .id();
-
-    map[position][tile_type] = Some(spawned_tile);
+    transform
 }
 
-
+pub fn chunk_of (position: IVec3) -> IVec3 {
+    IVec3::new(
+        position.x.div_euclid(CHUNK_SIZE),
+        position.y.div_euclid(CHUNK_SIZE),
+        position.z.div_euclid(CHUNK_SIZE),
+    )
+}
 
 // Resources
-#[derive(Clone, Deref, DerefMut)]
-pub struct GridMap (Array3<EnumMap<TileType, Option<Entity>>>);
+// The map is split into chunks that are only allocated once something is written to them.
+// Any position can be written to, the map grows to fit. Reading from a missing chunk gives an empty position.
+#[derive(Default, Clone)]
+pub struct GridMap {
+    chunks: HashMap<IVec3, Array3<GridCell>>,
+    // The smallest box of positions holding every allocated chunk, if any are.
+    bounds: Option<(IVec3, IVec3)>,
+    // Entities that belong to individual tiles, like doors. Most tiles have none.
+    entities: HashMap<(IVec3, TileType), Entity>,
+    // Chunks that need rebuilding even though none of their tiles changed.
+    dirty_chunks: HashSet<IVec3>,
+    changes: Vec<TileChanged>,
+    empty: GridCell,
+}
 impl GridMap {
+    pub fn new() -> GridMap {
+        GridMap::default()
+    }
+
     pub fn width(&self) -> i32 {
-        self.len_of(Axis(0)) as i32
+        self.max().x - self.min().x + 1
     }
     pub fn height(&self) -> i32 {
-        self.len_of(Axis(1)) as i32
+        self.max().y - self.min().y + 1
     }
     pub fn length(&self) -> i32 {
-        self.len_of(Axis(2)) as i32
+        self.max().z - self.min().z + 1
     }
 
+    // An empty map has its max below its min, so it has no positions.
     pub fn min(&self) -> IVec3 {
-        IVec3::new(0, 0, 0)
+        self.bounds.map_or(IVec3::ZERO, |(min, _)| min)
     }
     pub fn max(&self) -> IVec3 {
-        IVec3::new(self.width() - 1, self.height() - 1, self.length() - 1)
+        self.bounds.map_or(-IVec3::ONE, |(_, max)| max)
     }
 
+    // True if the position is outside every allocated chunk, so nothing can have been placed there yet.
     pub fn position_oob(&self, position: IVec3) -> bool {
-        let min = self.min();
-        let max = self.max();
+        !self.chunks.contains_key(&chunk_of(position))
+    }
 
-        position.x < min.x || position.y < min.y || position.z < min.z ||
-        position.x > max.x || position.y > max.y || position.z > max.z
+    // True if anything at all has been placed at the position.
+    pub fn position_occupied(&self, position: IVec3) -> bool {
+        self[position] != TileType::empty()
     }
 
+    // True if any tile at the position is solid.
     pub fn position_collides(&self, position: IVec3) -> bool {
-        self[position] != TileType::empty()
+        self[position].values().flatten().any(|data| data.flags.contains(TileFlags::SOLID))
     }
-}
-impl Default for GridMap {
-    fn default() -> Self {
-        GridMap(Array3::<EnumMap<TileType, Option<Entity>>>::from_elem(
-            (80, 10, 40),
-            enum_map ! {
-                _ => None
-            }
-        ),)
+
+    pub fn tile_has(&self, position: IVec3, tile_type: TileType, flags: TileFlags) -> bool {
+        self[position][tile_type].is_some_and(|data| data.flags.contains(flags))
+    }
+
+    pub fn entity(&self, position: IVec3, tile_type: TileType) -> Option<Entity> {
+        self.entities.get(&(position, tile_type)).copied()
+    }
+
+    pub fn set_entity(&mut self, position: IVec3, tile_type: TileType, entity: Entity) {
+        self.entities.insert((position, tile_type), entity);
+    }
+
+    pub fn take_entity(&mut self, position: IVec3, tile_type: TileType) -> Option<Entity> {
+        self.entities.remove(&(position, tile_type))
+    }
+
+    // Writes a slot and records the change if it actually changed anything.
+    pub fn set(&mut self, position: IVec3, tile_type: TileType, data: Option<TileData>) {
+        let old = self[position][tile_type];
+        if old == data {
+            return;
+        }
+
+        self[position][tile_type] = data;
+        self.changes.push(TileChanged { position, tile_type, old, new: data });
+    }
+
+    pub fn has_changes(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    pub fn take_changes(&mut self) -> Vec<TileChanged> {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn has_dirty_chunks(&self) -> bool {
+        !self.dirty_chunks.is_empty()
+    }
+
+    pub fn take_dirty_chunks(&mut self) -> Vec<IVec3> {
+        self.dirty_chunks.drain().collect()
+    }
+
+    pub fn mark_dirty(&mut self, chunk: IVec3) {
+        self.dirty_chunks.insert(chunk);
+    }
+
+    pub fn allocated_chunks(&self) -> impl Iterator<Item = IVec3> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    // The first and last positions of a chunk, if it's allocated.
+    pub fn chunk_bounds(&self, chunk: IVec3) -> Option<(IVec3, IVec3)> {
+        if !self.chunks.contains_key(&chunk) {
+            return None;
+        }
+
+        let min = chunk * CHUNK_SIZE;
+        Some((min, min + IVec3::splat(CHUNK_SIZE - 1)))
+    }
+
+    pub fn chunk_positions(&self, chunk: IVec3) -> WithinBoxIterator {
+        match self.chunk_bounds(chunk) {
+            Some((min, max)) => WithinBoxIterator::new(min, max),
+            // A box whose min is past its max yields nothing.
+            None => WithinBoxIterator::new(IVec3::ONE, IVec3::ZERO),
+        }
+    }
+
+    fn local_index(position: IVec3) -> [usize; 3] {
+        let local = position - chunk_of(position) * CHUNK_SIZE;
+        [local.x as usize, local.y as usize, local.z as usize]
     }
 }
 impl Index<IVec3> for GridMap {
-    type Output = EnumMap<TileType, Option<Entity>>;
+    type Output = GridCell;
 
     fn index(&self, index: IVec3) -> &Self::Output {
-        &self.0[[index.x as usize, index.y as usize, index.z as usize]]
+        match self.chunks.get(&chunk_of(index)) {
+            Some(chunk) => &chunk[GridMap::local_index(index)],
+            None => &self.empty,
+        }
     }
 }
 impl IndexMut<IVec3> for GridMap {
     fn index_mut(&mut self, index: IVec3) -> &mut Self::Output {
-        &mut self.0[[index.x as usize, index.y as usize, index.z as usize]]
+        let chunk = chunk_of(index);
+
+        if !self.chunks.contains_key(&chunk) {
+            let min = chunk * CHUNK_SIZE;
+            let max = min + IVec3::splat(CHUNK_SIZE - 1);
+            self.bounds = Some(match self.bounds {
+                Some((old_min, old_max)) => (old_min.min(min), old_max.max(max)),
+                None => (min, max),
+            });
+        }
+
+        let size = CHUNK_SIZE as usize;
+        let chunk = self.chunks.entry(chunk).or_insert_with(|| {
+            Array3::<GridCell>::from_elem(
+                (size, size, size),
+                enum_map ! {
+                    _ => None
+                }
+            )
+        });
+
+        &mut chunk[GridMap::local_index(index)]
     }
 }
 impl IntoIterator for GridMap {
@@ -153,10 +262,44 @@ impl Default for MapScale {
         MapScale(Vec3::new(2.0, 2.0, 2.0))
     }
 }
+impl MapScale {
+    // Grid space has a position's center at its integer coordinates.
+    pub fn world_to_grid(&self, point: Vec3) -> Vec3 {
+        point / self.0
+    }
+    pub fn grid_to_world(&self, point: Vec3) -> Vec3 {
+        point * self.0
+    }
+
+    // The position a world space point is inside of.
+    pub fn world_to_cell(&self, point: Vec3) -> IVec3 {
+        self.world_to_grid(point).round().as_ivec3()
+    }
+    // The world space center of a position.
+    pub fn cell_to_world(&self, position: IVec3) -> Vec3 {
+        self.grid_to_world(position.as_vec3())
+    }
+}
+
+// Events
+// Sent once per changed tile slot, late in the frame the slot changed. Everything derived from the map keeps itself in sync by
+// reading these: rebuild_chunks (meshes and colliders), invalidate_paths, update_explored and draw_automap.
+// Map lighting isn't built from tiles, so there's nothing to update for it.
+#[derive(Debug, Clone, Copy)]
+pub struct TileChanged {
+    pub position: IVec3,
+    pub tile_type: TileType,
+    pub old: Option<TileData>,
+    pub new: Option<TileData>,
+}
 
 // Data
-#[derive(Enum, Clone, Copy, Debug, PartialEq)]
+// Every tile slot of a single grid position.
+pub type GridCell = EnumMap<TileType, Option<TileData>>;
+
+#[derive(Enum, Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TileType {
+    #[default]
     Center,
     Ceiling,
     Floor,
@@ -188,13 +331,144 @@ impl TileType {
         }
     }
 
-    pub fn empty() -> EnumMap<TileType, Option<Entity>> {
-        EnumMap::<TileType, Option<Entity>>::default()
+    pub fn opposite(&self) -> TileType {
+        match self {
+            TileType::North => TileType::South,
+            TileType::East => TileType::West,
+            TileType::South => TileType::North,
+            TileType::West => TileType::East,
+            TileType::Ceiling => TileType::Floor,
+            TileType::Floor => TileType::Ceiling,
+            TileType::Center => TileType::Center,
+        }
+    }
+
+    // The step to the neighbouring position on this side.
+    pub fn direction(&self) -> IVec3 {
+        let translation = TileOffsets::default()[*self].translation * 2.0;
+        IVec3::new(translation.x as i32, translation.y as i32, translation.z as i32)
+    }
+
+    // The side facing along an axis (an index into an IVec3) in the direction of sign.
+    pub fn from_axis(axis: usize, sign: i32) -> TileType {
+        match (axis, sign > 0) {
+            (0, true) => TileType::East,
+            (0, false) => TileType::West,
+            (1, true) => TileType::Ceiling,
+            (1, false) => TileType::Floor,
+            (_, true) => TileType::North,
+            (_, false) => TileType::South,
+        }
+    }
+
+    // The axis a face of this type is flat along, followed by the two axes it spans.
+    // Axes are indices into an IVec3.
+    pub fn plane_axes(&self) -> (usize, usize, usize) {
+        match self {
+            TileType::North | TileType::South => (2, 0, 1),
+            TileType::East | TileType::West => (0, 2, 1),
+            TileType::Center | TileType::Ceiling | TileType::Floor => (1, 0, 2),
+        }
+    }
+
+    pub fn empty() -> GridCell {
+        GridCell::default()
     }
 }
-impl Default for TileType {
-    fn default() -> Self {
-        TileType::Center
+
+// What goes in a tile slot.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileData {
+    pub kind: TileKind,
+    pub material: TileMaterial,
+    pub flags: TileFlags,
+}
+impl TileData {
+    pub fn new(kind: TileKind, material: TileMaterial) -> TileData {
+        TileData { kind, material, flags: kind.default_flags() }
+    }
+
+    pub fn with_flags(mut self, flags: TileFlags) -> TileData {
+        self.flags = flags;
+        self
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TileKind {
+    #[default]
+    Wall,
+    Floor,
+    Ceiling,
+    Door,
+    Liquid,
+    Stairs,
+}
+impl TileKind {
+    pub fn default_flags(&self) -> TileFlags {
+        match self {
+            TileKind::Wall => TileFlags::SOLID,
+            TileKind::Floor => TileFlags::SOLID | TileFlags::WALKABLE,
+            TileKind::Ceiling => TileFlags::SOLID,
+            TileKind::Door => TileFlags::SOLID | TileFlags::DOOR,
+            TileKind::Liquid => TileFlags::WALKABLE | TileFlags::TRANSPARENT | TileFlags::LIQUID,
+            TileKind::Stairs => TileFlags::WALKABLE | TileFlags::TRANSPARENT | TileFlags::STAIRS,
+        }
+    }
+}
+
+// Which entry of TileAssets a tile is drawn with.
+#[derive(Enum, Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TileMaterial {
+    Grass,
+    GrayMediumBrick,
+    #[default]
+    Concrete,
+    CarpetRed,
+    WoodPlanks,
+}
+impl TileMaterial {
+    // How much digging it takes to break through a tile of this material.
+    pub fn durability(&self) -> f32 {
+        match self {
+            TileMaterial::Grass => 1.0,
+            TileMaterial::CarpetRed => 2.0,
+            TileMaterial::WoodPlanks => 3.0,
+            TileMaterial::Concrete => 6.0,
+            TileMaterial::GrayMediumBrick => 8.0,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileFlags (u8);
+impl TileFlags {
+    pub const NONE: TileFlags = TileFlags(0);
+    pub const SOLID: TileFlags = TileFlags(1 << 0);
+    pub const WALKABLE: TileFlags = TileFlags(1 << 1);
+    pub const TRANSPARENT: TileFlags = TileFlags(1 << 2);
+    pub const DOOR: TileFlags = TileFlags(1 << 3);
+    pub const LIQUID: TileFlags = TileFlags(1 << 4);
+    // Lets actors climb between this position and the one above. Goes in the Center slot.
+    pub const STAIRS: TileFlags = TileFlags(1 << 5);
+
+    pub fn contains(&self, other: TileFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: TileFlags) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: TileFlags) {
+        self.0 &= !other.0;
+    }
+}
+impl std::ops::BitOr for TileFlags {
+    type Output = TileFlags;
+
+    fn bitor(self, rhs: TileFlags) -> TileFlags {
+        TileFlags(self.0 | rhs.0)
     }
 }
 
@@ -203,14 +477,6 @@ pub struct Transformation {
     pub translation: Vec3,
     pub rotation: Vec3,
 }
-impl Transformation {
-    fn with_translation(translate: Vec3) -> Transformation {
-        Transformation {
-            translation: translate,
-            rotation: Vec3::default(),
-        }
-    }
-}
 
 #[derive(Clone, Copy, Deref)]
 pub struct TileOffsets (EnumMap<TileType, Transformation>);
@@ -227,8 +493,6 @@ impl Default for TileOffsets {
                 TileType::East => Transformation {translation: Vec3::new(0.5, 0.0, 0.0), rotation: Vec3::new(90.0_f32.to_radians(), -90.0_f32.to_radians(), 0.0)},
                 TileType::South => Transformation {translation: Vec3::new(0.0, 0.0, -0.5), rotation: Vec3::new(90.0_f32.to_radians(), 0.0, 0.0)},
                 TileType::West => Transformation {translation: Vec3::new(-0.5, 0.0, 0.0), rotation: Vec3::new(-90.0_f32.to_radians(), -90.0_f32.to_radians(), 0.0)},
-
-                _ => Transformation {translation: Vec3::new(0.0, 0.0, 0.0), rotation: Vec3::new(0.0, 0.0, 0.0)}
             }
         )
     }
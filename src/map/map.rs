@@ -1,6 +1,3 @@
-use std::f32::consts::E;
-use std::ops::Deref;
-
 use bevy::prelude::*;
 
 pub mod geometric;
@@ -8,20 +5,40 @@ pub use geometric::*;
 
 pub mod grid;
 pub use grid::*;
+
+pub mod mesher;
+pub use mesher::*;
+
+pub mod collider;
+pub use collider::*;
+
+pub mod portal;
+pub use portal::*;
+
+pub mod streaming;
+pub use streaming::*;
+
+pub mod pathfinding;
+pub use pathfinding::*;
+
+pub mod sight;
+pub use sight::*;
+
+pub mod fog;
+pub use fog::*;
+
+pub mod raycast;
+pub use raycast::*;
 use iyes_loopless::state::NextState;
-use rand::{Rng, prelude::SliceRandom};
+use rand::{Rng, SeedableRng, prelude::SliceRandom, rngs::StdRng};
 
 use super::GameState;
-use super::assets::TileAssets;
 
 // Rooms are equivalent to Nodes. Branches are equivalent to Edges.
 
 const MIN_ROOMS: usize = 3;
 const MAX_ROOMS: i32 = 30;
 
-const MIN_BRANCHES_FROM_ROOM: i32 = 1;
-const MAX_BRANCHES_FROM_ROOM: i32 = 3;
-
 // Room/Node generation
 const MIN_SIZE: i32 = 6;
 const MAX_SIZE: i32 = 10;
@@ -40,16 +57,56 @@ pub struct MapPlugin;
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_event::<TileChanged>()
             .init_resource::<RoomSpawnAttempts>()
             .init_resource::<GridMap>()
+            .init_resource::<ChunkMeshes>()
+            .init_resource::<ChunkColliders>()
+            .init_resource::<RegionMap>()
+            .init_resource::<PortalGraph>()
+            .init_resource::<VisibleRegions>()
+            .init_resource::<UnloadedChunks>()
+            .init_resource::<Explored>()
+            .init_resource::<MapRng>()
+            .init_resource::<GenerationBounds>()
             .init_resource::<MapScale>();
     }
 }
 
 // Systems
+// Throws away the whole map, and everything built from it, so a new one can be generated.
+pub fn clear_map (
+    mut map: ResMut<GridMap>,
+    mut regions: ResMut<RegionMap>,
+    mut graph: ResMut<PortalGraph>,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    mut chunk_colliders: ResMut<ChunkColliders>,
+    mut unloaded: ResMut<UnloadedChunks>,
+    mut visible_regions: ResMut<VisibleRegions>,
+
+    map_entity_query: Query<Entity, Or<(With<Rect3Room>, With<PathExit>, With<HoleEntrance>, With<ChunkMesh>)>>,
+
+    mut commands: Commands,
+) {
+    for entity in map_entity_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (_, entity) in chunk_colliders.drain() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    *map = GridMap::default();
+    regions.clear();
+    graph.clear();
+    chunk_meshes.clear();
+    unloaded.clear();
+    *visible_regions = VisibleRegions::default();
+}
+
 pub fn map_branching_start (
-    map: Res<GridMap>,
-    tiles: Res<TileAssets>,
+    bounds: Res<GenerationBounds>,
+    mut explored: ResMut<Explored>,
+    mut map_rng: ResMut<MapRng>,
 
     mut room_spawn_attempts: ResMut<RoomSpawnAttempts>,
 
@@ -57,19 +114,21 @@ pub fn map_branching_start (
 ) {
     println!("starting map gen");
 
-    let mut rng = rand::thread_rng();
+    *explored = Explored::default();
+
+    let rng = &mut **map_rng;
     
     let w = rng.gen_range(MIN_SIZE..=MAX_SIZE);
     let h = rng.gen_range(MIN_HEIGHT..=MAX_HEIGHT);
     let l = rng.gen_range(MIN_SIZE..=MAX_SIZE);
-    let x = rng.gen_range(0..(map.width() - w));
-    let y = rng.gen_range(0..(map.height() - h));
-    let z = rng.gen_range(0..(map.length() - l));
+    let x = rng.gen_range(bounds.min.x..=(bounds.max.x - w));
+    let y = rng.gen_range(bounds.min.y..=(bounds.max.y - h));
+    let z = rng.gen_range(bounds.min.z..=(bounds.max.z - l));
 
     let room = Rect3Room {
-        ceiling: tiles.concrete.clone(),
-        walls: tiles.gray_medium_brick.clone(),
-        floor: tiles.concrete.clone(),
+        ceiling: TileData::new(TileKind::Ceiling, TileMaterial::Concrete),
+        walls: TileData::new(TileKind::Wall, TileMaterial::GrayMediumBrick),
+        floor: TileData::new(TileKind::Floor, TileMaterial::Concrete),
         rect: Rect3::new(IVec3::new(x, y, z), w, h, l),
 
         ..default()
@@ -91,7 +150,10 @@ pub fn map_branching_start (
 
 pub fn map_branching_generation (
     mut map: ResMut<GridMap>,
+    mut regions: ResMut<RegionMap>,
     mut room_spawn_attempts: ResMut<RoomSpawnAttempts>,
+    mut map_rng: ResMut<MapRng>,
+    bounds: Res<GenerationBounds>,
 
     mut room_query: ParamSet<(
         Query<(Entity, &Entrances, &Exits), With<Rect3Room>>,
@@ -106,7 +168,7 @@ pub fn map_branching_generation (
 ) {
     println!("branching gen");
 
-    let mut rng = rand::thread_rng();
+    let rng = &mut **map_rng;
 
     let room_query_0 = room_query.p0();
     let rooms = room_query_0.iter().collect::<Vec<(Entity, &Entrances, &Exits)>>();
@@ -122,6 +184,7 @@ pub fn map_branching_generation (
             for position in &*map {
                 clear_position(&mut commands, &mut map, position);
             }
+            regions.clear();
             for (entity, _, _) in &rooms {
                 commands.entity(*entity).despawn();
             }
@@ -152,7 +215,7 @@ pub fn map_branching_generation (
     }
      */
 
-    let room_entity = rooms.choose_weighted(&mut rng, |(_ent, entrances, exits)| 1.0 / (entrances.len() + exits.len() + 1) as f32).unwrap().0;
+    let room_entity = rooms.choose_weighted(rng, |(_ent, entrances, exits)| 1.0 / (entrances.len() + exits.len() + 1) as f32).unwrap().0;
 
     if let Ok((room, entrances, mut exits)) = room_query.p1().get_mut(room_entity) {
         //let mut entrances = Vec::new();
@@ -161,7 +224,7 @@ pub fn map_branching_generation (
 
         // This feels clunky and messy and a bit out of place. Perhaps it should be handled differently?? Not sure how though. It might be fine tbh.
         for entrance_entity in entrances.iter() {
-            if let Ok((entrance_entity, entrance)) = entrance_query.get(*entrance_entity) {
+            if let Ok((_, entrance)) = entrance_query.get(*entrance_entity) {
                 exclude.push(entrance.position);
             }
             else {
@@ -173,7 +236,7 @@ pub fn map_branching_generation (
         }
 
         for exit_entity in exits.iter() {
-            if let Ok((exit_entity, exit)) = exit_query.get(*exit_entity) {
+            if let Ok((_, exit)) = exit_query.get(*exit_entity) {
                 exclude.push(exit.path[0].position);
             }
             else {
@@ -182,9 +245,9 @@ pub fn map_branching_generation (
         }
 
         let mut exit = PathExit {
-            ceiling: room.ceiling.clone(),
-            walls: room.walls.clone(),
-            floor: room.floor.clone(),
+            ceiling: room.ceiling,
+            walls: room.walls,
+            floor: room.floor,
             ..default()
         };
 
@@ -192,7 +255,7 @@ pub fn map_branching_generation (
 
         let mut can_spawn_room = true;
 
-        if let Some((exit_point, exit_orientation)) = random_surface_wall_point(exclude, room.rect, &*map) {
+        if let Some((exit_point, exit_orientation)) = random_surface_wall_point(exclude, room.rect, &map, rng) {
             // TODO: Get to work on path generation.
             let mut vector = TileOffsets::default()[exit_orientation].translation * 2.0;
             let mut current_point = exit_point;
@@ -218,7 +281,7 @@ pub fn map_branching_generation (
                     }
 
                     // Check if path is out of bounds
-                    if map.position_oob(current_point) {
+                    if !bounds.contains(current_point) {
                         can_spawn_room = false;
                         break 'path;
                     }
@@ -228,7 +291,7 @@ pub fn map_branching_generation (
                     }
 
                     // Check if path intersects with anything else
-                    if map.position_collides(current_point){
+                    if map.position_occupied(current_point){
                         can_spawn_room = false;
                         break 'path;
                     }
@@ -264,7 +327,7 @@ pub fn map_branching_generation (
                 path_positions.pop();
 
                 for position in rect {
-                    if map.position_oob(position) || map.position_collides(position) || path_positions.contains(&position) {
+                    if !bounds.contains(position) || map.position_occupied(position) || path_positions.contains(&position) {
                         is_ok = false;
                         break;
                     }
@@ -272,9 +335,9 @@ pub fn map_branching_generation (
 
                 if is_ok {
                     let room = Rect3Room {
-                        ceiling: exit.ceiling.clone(),
-                        walls: exit.walls.clone(),
-                        floor: exit.floor.clone(),
+                        ceiling: exit.ceiling,
+                        walls: exit.walls,
+                        floor: exit.floor,
                         rect,
                 
                         ..default()
@@ -314,9 +377,9 @@ pub fn map_branching_generation (
 // TODO: Entities should be children of their room.
 pub fn spawn_rooms (
     mut map: ResMut<GridMap>,
-    map_scale: Res<MapScale>,
+    mut regions: ResMut<RegionMap>,
 
-    room_query: Query<(Entity, &Rect3Room), (Added<Rect3Room>)>,
+    room_query: Query<(Entity, &Rect3Room), Added<Rect3Room>>,
 
     mut commands: Commands,
 ) {
@@ -327,26 +390,27 @@ pub fn spawn_rooms (
 
         for position in room {
             clear_position(&mut commands, &mut map, position);
+            regions.insert(position, entity);
     
             
             if position.y == max.y {
-                spawn_tile(&mut commands, &mut map, &map_scale, room.ceiling.clone(), TileType::Ceiling, position);
+                place_tile(&mut map, room.ceiling, TileType::Ceiling, position);
             }
             if position.z == max.z {
-                spawn_tile(&mut commands, &mut map, &map_scale, room.walls.clone(), TileType::North, position);
+                place_tile(&mut map, room.walls, TileType::North, position);
             }
             if position.x == max.x {
-                spawn_tile(&mut commands, &mut map, &map_scale, room.walls.clone(), TileType::East, position);
+                place_tile(&mut map, room.walls, TileType::East, position);
             }
 
             if position.y == min.y {
-                spawn_tile(&mut commands, &mut map, &map_scale, room.floor.clone(), TileType::Floor, position);
+                place_tile(&mut map, room.floor, TileType::Floor, position);
             }
             if position.z == min.z {
-                spawn_tile(&mut commands, &mut map, &map_scale, room.walls.clone(), TileType::South, position);
+                place_tile(&mut map, room.walls, TileType::South, position);
             }
             if position.x == min.x {
-                spawn_tile(&mut commands, &mut map, &map_scale, room.walls.clone(), TileType::West, position);
+                place_tile(&mut map, room.walls, TileType::West, position);
             }
         }
     }
@@ -355,9 +419,9 @@ pub fn spawn_rooms (
 // TODO: Entities should be children of their path.
 pub fn spawn_exits (
     mut map: ResMut<GridMap>,
-    map_scale: Res<MapScale>,
+    mut regions: ResMut<RegionMap>,
 
-    path_query: Query<(Entity, &PathExit), (Added<PathExit>)>,
+    path_query: Query<(Entity, &PathExit), Added<PathExit>>,
 
     mut commands: Commands,
 ) {
@@ -376,25 +440,26 @@ pub fn spawn_exits (
             // Anywhere inbetween
             else {
                 clear_position(&mut commands, &mut map, p.position);
+                regions.insert(p.position, entity);
 
                 if exit.path[i+1].orientation == p.orientation {
-                    spawn_tile(&mut commands, &mut map, &map_scale, exit.walls.clone(), p.orientation.rotate90(true), p.position);
-                    spawn_tile(&mut commands, &mut map, &map_scale, exit.walls.clone(), p.orientation.rotate90(false), p.position);
+                    place_tile(&mut map, exit.walls, p.orientation.rotate90(true), p.position);
+                    place_tile(&mut map, exit.walls, p.orientation.rotate90(false), p.position);
                 }
                 else if exit.path[i+1].orientation == p.orientation.rotate90(true) {
-                    spawn_tile(&mut commands, &mut map, &map_scale, exit.walls.clone(), p.orientation, p.position);
-                    spawn_tile(&mut commands, &mut map, &map_scale, exit.walls.clone(), p.orientation.rotate90(false), p.position);
+                    place_tile(&mut map, exit.walls, p.orientation, p.position);
+                    place_tile(&mut map, exit.walls, p.orientation.rotate90(false), p.position);
                 }
                 else if exit.path[i+1].orientation == p.orientation.rotate90(false) {
-                    spawn_tile(&mut commands, &mut map, &map_scale, exit.walls.clone(), p.orientation, p.position);
-                    spawn_tile(&mut commands, &mut map, &map_scale, exit.walls.clone(), p.orientation.rotate90(true), p.position);
+                    place_tile(&mut map, exit.walls, p.orientation, p.position);
+                    place_tile(&mut map, exit.walls, p.orientation.rotate90(true), p.position);
                 }
                 else {
                     panic!("Malformed path!");
                 }
 
-                spawn_tile(&mut commands, &mut map, &map_scale, exit.ceiling.clone(), TileType::Ceiling, p.position);
-                spawn_tile(&mut commands, &mut map, &map_scale, exit.floor.clone(), TileType::Floor, p.position);
+                place_tile(&mut map, exit.ceiling, TileType::Ceiling, p.position);
+                place_tile(&mut map, exit.floor, TileType::Floor, p.position);
 
 
             }
@@ -430,10 +495,10 @@ impl Iterator for WithinBoxIterator {
             self.position.z += 1;
         }
         if self.position.z > self.max.z {
-            return None
+            None
         }
         else {
-            return Some(self.position)
+            Some(self.position)
         }
     }
 }
@@ -445,4 +510,40 @@ impl WithinBoxIterator {
 
 // Resources
 #[derive(Default, Deref, DerefMut, Clone)]
-pub struct RoomSpawnAttempts(i32);
\ No newline at end of file
+pub struct RoomSpawnAttempts(i32);
+
+// The box generation places rooms and paths inside, in cells. The map itself has no edges,
+// this only keeps a generated floor to a chosen size.
+#[derive(Clone, Copy, Debug)]
+pub struct GenerationBounds {
+    pub min: IVec3,
+    pub max: IVec3,
+}
+impl Default for GenerationBounds {
+    fn default() -> Self {
+        GenerationBounds {
+            min: IVec3::ZERO,
+            max: IVec3::new(79, 9, 39),
+        }
+    }
+}
+impl GenerationBounds {
+    pub fn contains(&self, position: IVec3) -> bool {
+        position.cmpge(self.min).all() && position.cmple(self.max).all()
+    }
+}
+
+// Everything random about generation comes from here, so the same seed always builds the same map.
+// Restarting a failed generation keeps drawing from the same generator rather than reseeding.
+#[derive(Deref, DerefMut, Clone)]
+pub struct MapRng (StdRng);
+impl MapRng {
+    pub fn new(seed: u64) -> MapRng {
+        MapRng(StdRng::seed_from_u64(seed))
+    }
+}
+impl Default for MapRng {
+    fn default() -> Self {
+        MapRng::new(rand::random())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::render::mesh::{VertexAttributeValues::*, Indices, PrimitiveTopology};
+
+use crate::assets::{generate_tangents, world_space_uvs, TileAssets};
+
+use super::{GridMap, MapScale, TileData, TileMaterial, TileOffsets, TileType, tile_transform, ChunkColliders, spawn_chunk_collider, RegionMap, RegionMesh, UnloadedChunks, TileChanged, chunk_of};
+
+// Chunks are cubes of this many cells per side.
+pub const CHUNK_SIZE: i32 = 16;
+
+// Systems
+// Remeshes and recollides every chunk that had a tile change or was marked dirty.
+// Unloaded chunks just lose their meshes and colliders.
+pub fn rebuild_chunks (
+    mut map: ResMut<GridMap>,
+    mut ev_tile_changed: EventReader<TileChanged>,
+    map_scale: Res<MapScale>,
+    tiles: Res<TileAssets>,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    mut chunk_colliders: ResMut<ChunkColliders>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    regions: Res<RegionMap>,
+    unloaded: Res<UnloadedChunks>,
+
+    mut commands: Commands,
+) {
+    for ev in ev_tile_changed.iter() {
+        map.mark_dirty(chunk_of(ev.position));
+    }
+
+    if !map.has_dirty_chunks() {
+        return;
+    }
+
+    for chunk in map.take_dirty_chunks() {
+        if let Some(entities) = chunk_meshes.remove(&chunk) {
+            for entity in entities {
+                commands.entity(entity).despawn();
+            }
+        }
+
+        if let Some(entity) = chunk_colliders.remove(&chunk) {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        if unloaded.contains(&chunk) {
+            continue;
+        }
+
+        let faces = greedy_faces(&map, &regions, chunk);
+
+        if let Some(entity) = spawn_chunk_collider(&mut commands, &meshes, &tiles, &map_scale, &faces) {
+            chunk_colliders.insert(chunk, entity);
+        }
+
+        // Meshes are split by region as well as material so portal culling can hide them separately.
+        let mut builders = HashMap::<(Option<Entity>, TileMaterial), MeshBuilder>::new();
+
+        for face in faces.iter() {
+            if let Some(mesh) = meshes.get(&tiles.get(face.data.material).mesh) {
+                let (transform, stretch) = face.placement(&map_scale);
+                builders.entry((face.region, face.data.material)).or_default().append(mesh, &transform, stretch);
+            }
+        }
+
+        let entities = builders.into_iter()
+            .map(|((region, material), builder)| {
+                let mut entity = commands.spawn_bundle(PbrBundle {
+                    mesh: meshes.add(builder.build()),
+                    material: tiles.get(material).material.clone(),
+                    ..default()
+                });
+                entity.insert(ChunkMesh(chunk));
+
+                if let Some(region) = region {
+                    entity.insert(RegionMesh(region));
+                }
+
+                entity.id()
+            })
+            .collect::<Vec<Entity>>();
+
+        if !entities.is_empty() {
+            chunk_meshes.insert(chunk, entities);
+        }
+    }
+}
+
+// Helper Functions
+// Merges the faces of a chunk into as few rectangles as possible.
+// Faces only merge with faces of the same type, on the same layer, with the same data and region.
+pub fn greedy_faces (map: &GridMap, regions: &RegionMap, chunk: IVec3) -> Vec<FaceRect> {
+    let mut faces = Vec::new();
+
+    let (min, max) = match map.chunk_bounds(chunk) {
+        Some(bounds) => bounds,
+        None => return faces,
+    };
+
+    for (tile_type, _) in TileOffsets::default().iter() {
+        let (n, u, v) = tile_type.plane_axes();
+        let du = (max[u] - min[u] + 1) as usize;
+        let dv = (max[v] - min[v] + 1) as usize;
+
+        for layer in min[n]..=max[n] {
+            let mut mask = vec![None; du * dv];
+
+            for j in 0..dv {
+                for i in 0..du {
+                    let mut position = IVec3::ZERO;
+                    position[n] = layer;
+                    position[u] = min[u] + i as i32;
+                    position[v] = min[v] + j as i32;
+
+                    mask[i + j * du] = map[position][tile_type]
+                        .map(|data| (regions.get(&position).copied(), data));
+                }
+            }
+
+            for j in 0..dv {
+                let mut i = 0;
+                while i < du {
+                    let key = match mask[i + j * du] {
+                        Some(key) => key,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+
+                    let mut w = 1;
+                    while i + w < du && mask[i + w + j * du].as_ref() == Some(&key) {
+                        w += 1;
+                    }
+
+                    let mut h = 1;
+                    'grow: while j + h < dv {
+                        for k in 0..w {
+                            if mask[i + k + (j + h) * du].as_ref() != Some(&key) {
+                                break 'grow;
+                            }
+                        }
+                        h += 1;
+                    }
+
+                    for jj in 0..h {
+                        for k in 0..w {
+                            mask[i + k + (j + jj) * du] = None;
+                        }
+                    }
+
+                    let mut position = IVec3::ZERO;
+                    position[n] = layer;
+                    position[u] = min[u] + i as i32;
+                    position[v] = min[v] + j as i32;
+
+                    let mut size = IVec3::ONE;
+                    size[u] = w as i32;
+                    size[v] = h as i32;
+
+                    let (region, data) = key;
+                    faces.push(FaceRect { data, tile_type, region, min: position, size });
+
+                    i += w;
+                }
+            }
+        }
+    }
+
+    faces
+}
+
+// Components
+#[derive(Component, Deref, DerefMut, Clone, Copy)]
+pub struct ChunkMesh (pub IVec3);
+
+// Resources
+// The mesh entities spawned for each chunk, so they can be replaced on rebuild.
+#[derive(Default, Deref, DerefMut)]
+pub struct ChunkMeshes (HashMap<IVec3, Vec<Entity>>);
+
+// Data
+#[derive(Debug, Clone)]
+pub struct FaceRect {
+    pub data: TileData,
+    pub tile_type: TileType,
+    // The room or exit the face belongs to, if any.
+    pub region: Option<Entity>,
+    pub min: IVec3,
+    // Size in cells along each axis. Always 1 along the axis the face is flat on.
+    pub size: IVec3,
+}
+impl FaceRect {
+    // The transform of the rectangle's center and how far a unit tile mesh
+    // has to be stretched along its local x and z to cover the rectangle.
+    pub fn placement(&self, scale: &MapScale) -> (Transform, Vec2) {
+        let center = self.min.as_vec3() + (self.size - IVec3::ONE).as_vec3() / 2.0;
+        let transform = tile_transform(scale, self.tile_type, center);
+
+        let size = self.size.as_vec3();
+        let stretch = Vec2::new(
+            (transform.rotation * Vec3::X).abs().dot(size).round(),
+            (transform.rotation * Vec3::Z).abs().dot(size).round(),
+        );
+
+        (transform, stretch)
+    }
+}
+
+// Accumulates transformed copies of tile meshes into one mesh.
+#[derive(Default)]
+pub struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+impl MeshBuilder {
+    pub fn append(&mut self, mesh: &Mesh, transform: &Transform, stretch: Vec2) {
+        let (positions, normals) = match (mesh.attribute(Mesh::ATTRIBUTE_POSITION), mesh.attribute(Mesh::ATTRIBUTE_NORMAL)) {
+            (Some(Float32x3(positions)), Some(Float32x3(normals))) => (positions, normals),
+            _ => return,
+        };
+
+        let offset = self.positions.len() as u32;
+        let stretch = Vec3::new(stretch.x, 1.0, stretch.y);
+
+        for (position, normal) in positions.iter().zip(normals.iter()) {
+            let position = transform.mul_vec3(Vec3::from(*position) * stretch);
+            let normal = (transform.rotation * Vec3::from(*normal)).normalize_or_zero();
+            self.positions.push(position.into());
+            self.normals.push(normal.into());
+        }
+
+        match mesh.indices() {
+            Some(Indices::U16(indices)) => self.indices.extend(indices.iter().map(|i| *i as u32 + offset)),
+            Some(Indices::U32(indices)) => self.indices.extend(indices.iter().map(|i| *i + offset)),
+            None => self.indices.extend((0..positions.len() as u32).map(|i| i + offset)),
+        }
+    }
+
+    // Positions are already in world space, so uvs are projected with an identity transform.
+    pub fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+
+        world_space_uvs(&mut mesh, &Transform::identity());
+        generate_tangents(&mut mesh).unwrap();
+
+        mesh
+    }
+}
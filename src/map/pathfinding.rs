@@ -0,0 +1,323 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use crate::movement::CharacterController;
+
+use super::{GridMap, MapScale, TileChanged, TileFlags, TileType, CHUNK_SIZE};
+
+const STEP_COST: f32 = 1.0;
+const CLIMB_COST: f32 = 1.5;
+// Actors have to stop and open doors, and wading is slow.
+const DOOR_COST: f32 = 1.0;
+const LIQUID_COST: f32 = 2.0;
+
+// How close (in cells) an actor has to get to a waypoint before moving on to the next.
+const WAYPOINT_RADIUS: f32 = 0.2;
+
+// Systems
+// Finds paths for followers that have a goal but no path, or whose path went stale.
+pub fn update_paths (
+    map: Res<GridMap>,
+    map_scale: Res<MapScale>,
+
+    mut follower_query: Query<(&Transform, &mut PathFollower)>,
+) {
+    for (transform, mut follower) in follower_query.iter_mut() {
+        let goal = match follower.goal {
+            Some(goal) => goal,
+            None => continue,
+        };
+
+        if !follower.path.is_empty() && !follower.needs_repath {
+            continue;
+        }
+
+        let start = map_scale.world_to_cell(transform.translation);
+        follower.needs_repath = false;
+
+        match find_path(&map, start, goal, search_limit(&map)) {
+            // The first cell is the one we're already in.
+            Some(path) => follower.path = path.into_iter().skip(1).collect(),
+            None => {
+                follower.path.clear();
+                follower.goal = None;
+            }
+        }
+    }
+}
+
+// Any path through a changed position might not be walkable any more.
+pub fn invalidate_paths (
+    mut ev_tile_changed: EventReader<TileChanged>,
+
+    mut follower_query: Query<&mut PathFollower>,
+) {
+    let changed = ev_tile_changed.iter().map(|ev| ev.position).collect::<HashSet<IVec3>>();
+    if changed.is_empty() {
+        return;
+    }
+
+    for mut follower in follower_query.iter_mut() {
+        if follower.path.iter().any(|position| changed.contains(position)) {
+            follower.needs_repath = true;
+        }
+    }
+}
+
+// Steers followers towards their next waypoint through their character controller.
+pub fn follow_paths (
+    map_scale: Res<MapScale>,
+
+    mut follower_query: Query<(&Transform, &mut PathFollower, &mut CharacterController)>,
+) {
+    for (transform, mut follower, mut controller) in follower_query.iter_mut() {
+        let waypoint = match follower.path.first() {
+            Some(waypoint) => *waypoint,
+            None => {
+                if follower.goal.is_some() {
+                    follower.goal = None;
+                }
+                controller.wish = Vec3::ZERO;
+                continue;
+            }
+        };
+
+        let target = map_scale.cell_to_world(waypoint);
+        let mut offset = target - transform.translation;
+        offset.y = 0.0;
+
+        if offset.length() < WAYPOINT_RADIUS * map_scale.x && (target.y - transform.translation.y).abs() < map_scale.y * 0.5 {
+            follower.path.remove(0);
+            continue;
+        }
+
+        controller.wish = offset.normalize_or_zero() * follower.speed;
+    }
+}
+
+// Helper Functions
+// Every position a path can go through is in an allocated chunk, so no search needs to visit more than that.
+pub fn search_limit (map: &GridMap) -> usize {
+    map.allocated_chunks().count() * (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize
+}
+
+// A* over the grid. Returns every position from start to goal, both included.
+// Gives up after expanding max_search positions, so an unreachable goal doesn't have to walk everything.
+pub fn find_path (map: &GridMap, start: IVec3, goal: IVec3, max_search: usize) -> Option<Vec<IVec3>> {
+    if map.position_oob(start) || map.position_oob(goal) {
+        return None;
+    }
+
+    let heuristic = |position: IVec3| (goal - position).abs().as_vec3().dot(Vec3::ONE) * STEP_COST;
+
+    let mut open = BinaryHeap::new();
+    let mut came_from = HashMap::<IVec3, IVec3>::new();
+    let mut costs = HashMap::<IVec3, f32>::new();
+    // Positions whose cheapest cost is settled.
+    let mut closed = HashSet::<IVec3>::new();
+
+    open.push(SearchNode { priority: heuristic(start), cost: 0.0, position: start });
+    costs.insert(start, 0.0);
+
+    while let Some(SearchNode { cost, position, .. }) = open.pop() {
+        // Stale entries are left in the heap when a cheaper way to their position turns up.
+        if cost > costs[&position] || !closed.insert(position) {
+            continue;
+        }
+
+        if position == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(previous) = came_from.get(&current) {
+                path.push(*previous);
+                current = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        if closed.len() > max_search {
+            return None;
+        }
+
+        for (neighbour, step) in neighbours(map, position) {
+            if closed.contains(&neighbour) {
+                continue;
+            }
+
+            let new_cost = cost + step;
+            if costs.get(&neighbour).is_none_or(|old| new_cost < *old) {
+                costs.insert(neighbour, new_cost);
+                came_from.insert(neighbour, position);
+                open.push(SearchNode { priority: new_cost + heuristic(neighbour), cost: new_cost, position: neighbour });
+            }
+        }
+    }
+
+    None
+}
+
+// Every position an actor standing at the given one can move to, along with what the move costs.
+pub fn neighbours (map: &GridMap, position: IVec3) -> Vec<(IVec3, f32)> {
+    let mut neighbours = Vec::new();
+
+    for side in [TileType::North, TileType::East, TileType::South, TileType::West] {
+        let next = position + side.direction();
+        if map.position_oob(next) || !standable(map, next) {
+            continue;
+        }
+
+        if let Some(cost) = side_cost(map, position, side) {
+            let liquid = if map.tile_has(next, TileType::Floor, TileFlags::LIQUID) { LIQUID_COST } else { 0.0 };
+            neighbours.push((next, STEP_COST + cost + liquid));
+        }
+    }
+
+    // Stairs connect a position with the one above it.
+    let above = position + TileType::Ceiling.direction();
+    if map.tile_has(position, TileType::Center, TileFlags::STAIRS) && !map.position_oob(above) && side_cost(map, position, TileType::Ceiling).is_some() {
+        neighbours.push((above, CLIMB_COST));
+    }
+
+    let below = position + TileType::Floor.direction();
+    if !map.position_oob(below) && map.tile_has(below, TileType::Center, TileFlags::STAIRS) && side_cost(map, position, TileType::Floor).is_some() {
+        neighbours.push((below, CLIMB_COST));
+    }
+
+    neighbours
+}
+
+// Whether an actor can stand in a position without falling.
+pub fn standable (map: &GridMap, position: IVec3) -> bool {
+    map.tile_has(position, TileType::Floor, TileFlags::WALKABLE) || map.tile_has(position, TileType::Center, TileFlags::STAIRS)
+}
+
+// The extra cost of crossing from a position through one of its sides, or None if the way is blocked.
+// A side is blocked by a solid face on either this position or the neighbour facing it, unless it's a door.
+pub fn side_cost (map: &GridMap, position: IVec3, side: TileType) -> Option<f32> {
+    let next = position + side.direction();
+    let mut cost = 0.0;
+
+    for (cell, face) in [(position, side), (next, side.opposite())] {
+        if let Some(data) = map[cell][face] {
+            if data.flags.contains(TileFlags::DOOR) {
+                cost += DOOR_COST;
+            }
+            else if data.flags.contains(TileFlags::SOLID) {
+                return None;
+            }
+        }
+    }
+
+    Some(cost)
+}
+
+// Components
+// Moves an actor along a path to its goal. Set goal and the path is found for you.
+#[derive(Component, Default, Debug, Clone)]
+pub struct PathFollower {
+    pub goal: Option<IVec3>,
+    // Remaining waypoints, nearest first.
+    pub path: Vec<IVec3>,
+    pub speed: f32,
+    pub needs_repath: bool,
+}
+impl PathFollower {
+    pub fn new(speed: f32) -> PathFollower {
+        PathFollower { speed, ..default() }
+    }
+
+    pub fn go_to(&mut self, goal: IVec3) {
+        self.goal = Some(goal);
+        self.needs_repath = true;
+    }
+}
+
+// Data
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SearchNode {
+    priority: f32,
+    // What it cost to reach position when this node was pushed.
+    cost: f32,
+    position: IVec3,
+}
+impl Eq for SearchNode {}
+impl Ord for SearchNode {
+    // Reversed so the BinaryHeap pops the cheapest node first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.partial_cmp(&self.priority).unwrap_or(Ordering::Equal)
+    }
+}
+impl PartialOrd for SearchNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{place_tile, TileData, TileKind, TileMaterial};
+
+    // A one wide corridor running east from (0, 0, 1) to (6, 0, 1).
+    fn corridor() -> GridMap {
+        let mut map = GridMap::new();
+        for x in 0..=6 {
+            place_tile(&mut map, TileData::new(TileKind::Floor, TileMaterial::Concrete), TileType::Floor, IVec3::new(x, 0, 1));
+        }
+        map
+    }
+
+    #[test]
+    fn finds_straight_path() {
+        let map = corridor();
+        let path = find_path(&map, IVec3::new(1, 0, 1), IVec3::new(5, 0, 1), search_limit(&map)).unwrap();
+
+        assert_eq!(path, (1..=5).map(|x| IVec3::new(x, 0, 1)).collect::<Vec<IVec3>>());
+    }
+
+    #[test]
+    fn wall_blocks_path() {
+        let mut map = corridor();
+        place_tile(&mut map, TileData::new(TileKind::Wall, TileMaterial::Concrete), TileType::East, IVec3::new(3, 0, 1));
+
+        assert_eq!(find_path(&map, IVec3::new(1, 0, 1), IVec3::new(5, 0, 1), search_limit(&map)), None);
+    }
+
+    #[test]
+    fn door_lets_path_through() {
+        let mut map = corridor();
+        place_tile(&mut map, TileData::new(TileKind::Door, TileMaterial::WoodPlanks), TileType::East, IVec3::new(3, 0, 1));
+
+        let path = find_path(&map, IVec3::new(1, 0, 1), IVec3::new(5, 0, 1), search_limit(&map)).unwrap();
+        assert_eq!(path.len(), 5);
+    }
+    #[test]
+    fn followers_find_and_steer_along_paths() {
+        let map_scale = MapScale::default();
+        let mut follower = PathFollower::new(3.0);
+        follower.go_to(IVec3::new(5, 0, 1));
+
+        let mut world = World::new();
+        world.insert_resource(corridor());
+        world.insert_resource(map_scale.clone());
+        let actor = world.spawn()
+            .insert(Transform::from_translation(map_scale.cell_to_world(IVec3::new(1, 0, 1))))
+            .insert(follower)
+            .insert(CharacterController::default())
+            .id();
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_paths);
+        stage.add_system(follow_paths.after(update_paths));
+        stage.run(&mut world);
+
+        let follower = world.get::<PathFollower>(actor).unwrap();
+        assert_eq!(follower.path, (2..=5).map(|x| IVec3::new(x, 0, 1)).collect::<Vec<IVec3>>());
+
+        let controller = world.get::<CharacterController>(actor).unwrap();
+        assert!((controller.wish - Vec3::new(3.0, 0.0, 0.0)).length() < 1e-5);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::prelude::*;
+use bevy::render::{camera::PerspectiveProjection, primitives::{Aabb, Frustum}};
+
+use super::{Explored, HoleEntrance, MapScale, PathExit, TileOffsets, TileType};
+
+// Portals within this many cells of the camera are always looked through,
+// so turning around while standing in a doorway doesn't make rooms pop.
+const NEAR_PORTAL_DISTANCE: i32 = 1;
+
+// Systems
+// Links every exit to the regions at either end of its path.
+// Runs once generation is done, since rooms and exits keep changing until then.
+pub fn build_portal_graph (
+    mut graph: ResMut<PortalGraph>,
+    regions: Res<RegionMap>,
+
+    exit_query: Query<(Entity, &PathExit)>,
+    entrance_query: Query<&HoleEntrance>,
+) {
+    graph.clear();
+
+    for (entity, exit) in exit_query.iter() {
+        let (first, last) = match (exit.path.first(), exit.path.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+
+        let start = regions.get(&first.position).copied();
+        let end = regions.get(&last.position).copied();
+
+        // Paths this short have no cells of their own and just knock a hole between their ends.
+        if exit.path.len() < 3 {
+            if let (Some(start), Some(end)) = (start, end) {
+                if start != end {
+                    graph.add(Portal { regions: [start, end], position: first.position, side: first.orientation });
+                }
+            }
+            continue;
+        }
+
+        if let Some(start) = start {
+            graph.add(Portal { regions: [start, entity], position: first.position, side: first.orientation });
+        }
+        if let Some(end) = end.filter(|end| *end != entity) {
+            graph.add(Portal { regions: [entity, end], position: last.position, side: last.orientation.rotate90(true).rotate90(true) });
+        }
+    }
+
+    // Hole entrances are knocked into the side of the room a path leads to, from the path's last cell.
+    // Paths long enough to have cells of their own already added these, so only new pairs are linked.
+    for entrance in entrance_query.iter() {
+        let side = entrance.orientation.opposite();
+        let inside = regions.get(&entrance.position).copied();
+        let outside = regions.get(&(entrance.position + side.direction())).copied();
+
+        if let (Some(inside), Some(outside)) = (inside, outside) {
+            if inside != outside && !graph.connected(inside, outside) {
+                graph.add(Portal { regions: [inside, outside], position: entrance.position, side });
+            }
+        }
+    }
+
+    debug!("Built portal graph with {} portals", graph.portals.len());
+}
+
+// Walks the portal graph from the camera's region, only passing through portals the camera can see,
+// and hides the meshes of every region it didn't reach or the player hasn't explored yet.
+pub fn portal_culling (
+    map_scale: Res<MapScale>,
+    graph: Res<PortalGraph>,
+    regions: Res<RegionMap>,
+    mut visible_regions: ResMut<VisibleRegions>,
+    explored: Res<Explored>,
+
+    camera_query: Query<(&GlobalTransform, &Frustum), With<PerspectiveProjection>>,
+    mut mesh_query: Query<(&RegionMesh, &mut Visibility)>,
+) {
+    let (camera_transform, frustum) = match camera_query.iter().next() {
+        Some(camera) => camera,
+        None => return,
+    };
+
+    let cell = map_scale.world_to_cell(camera_transform.translation);
+
+    **visible_regions = regions.get(&cell).map(|start| {
+        let mut visible = HashSet::new();
+        let mut queue = VecDeque::new();
+
+        visible.insert(*start);
+        queue.push_back(*start);
+
+        while let Some(region) = queue.pop_front() {
+            for portal in graph.portals_of(region) {
+                let other = portal.other(region);
+                if visible.contains(&other) {
+                    continue;
+                }
+
+                let near = (portal.position - cell).abs().max_element() <= NEAR_PORTAL_DISTANCE;
+                if near || frustum.intersects_obb(&portal.aabb(&map_scale), &Mat4::IDENTITY, false) {
+                    visible.insert(other);
+                    queue.push_back(other);
+                }
+            }
+        }
+
+        visible
+    });
+
+    for (region_mesh, mut visibility) in mesh_query.iter_mut() {
+        let is_visible = visible_regions.is_visible(**region_mesh) && explored.region_explored(**region_mesh);
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+    }
+}
+
+// Components
+// The room or exit a chunk mesh was built for.
+#[derive(Component, Deref, DerefMut, Clone, Copy)]
+pub struct RegionMesh (pub Entity);
+
+// Resources
+// Which room or exit entity each cell belongs to.
+#[derive(Default, Deref, DerefMut, Clone)]
+pub struct RegionMap (HashMap<IVec3, Entity>);
+
+#[derive(Default, Clone)]
+pub struct PortalGraph {
+    pub portals: Vec<Portal>,
+    by_region: HashMap<Entity, Vec<usize>>,
+}
+impl PortalGraph {
+    pub fn add(&mut self, portal: Portal) {
+        let index = self.portals.len();
+        for region in portal.regions {
+            self.by_region.entry(region).or_default().push(index);
+        }
+        self.portals.push(portal);
+    }
+
+    pub fn clear(&mut self) {
+        self.portals.clear();
+        self.by_region.clear();
+    }
+
+    pub fn connected(&self, a: Entity, b: Entity) -> bool {
+        self.portals_of(a).any(|portal| portal.other(a) == b)
+    }
+
+    pub fn portals_of(&self, region: Entity) -> impl Iterator<Item = &Portal> {
+        self.by_region.get(&region)
+            .into_iter()
+            .flatten()
+            .map(|index| &self.portals[*index])
+    }
+}
+
+// None when the camera isn't inside any region, in which case everything is shown.
+#[derive(Default, Deref, DerefMut, Clone)]
+pub struct VisibleRegions (Option<HashSet<Entity>>);
+impl VisibleRegions {
+    pub fn is_visible(&self, region: Entity) -> bool {
+        match &self.0 {
+            Some(visible) => visible.contains(&region),
+            None => true,
+        }
+    }
+}
+
+// Data
+// An opening in the side of a cell that joins two regions.
+#[derive(Debug, Clone)]
+pub struct Portal {
+    pub regions: [Entity; 2],
+    pub position: IVec3,
+    pub side: TileType,
+}
+impl Portal {
+    pub fn other(&self, region: Entity) -> Entity {
+        if self.regions[0] == region {
+            self.regions[1]
+        } else {
+            self.regions[0]
+        }
+    }
+
+    pub fn aabb(&self, scale: &MapScale) -> Aabb {
+        let center = (self.position.as_vec3() + TileOffsets::default()[self.side].translation) * **scale;
+
+        let mut half_extents = Vec3::splat(0.5);
+        half_extents[self.side.plane_axes().0] = 0.05;
+        half_extents *= **scale;
+
+        Aabb::from_min_max(center - half_extents, center + half_extents)
+    }
+}
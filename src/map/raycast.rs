@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+
+use super::{GridMap, MapScale, TileType};
+
+// Helper Functions
+// The first tile face a ray hits, walking every position the ray passes through (a 3D DDA).
+// origin and max_distance are in world space. Center tiles aren't faces and are never hit.
+// Unallocated chunks are empty space, so the ray carries on through them until it leaves the map.
+pub fn raycast (map: &GridMap, scale: &MapScale, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+
+    let start = scale.world_to_grid(origin);
+    // How far the ray moves through grid space per world unit travelled.
+    let grid_direction = scale.world_to_grid(direction);
+
+    let (map_min, map_max) = (map.min(), map.max());
+    let mut position = scale.world_to_cell(origin);
+    let step = grid_direction.signum().as_ivec3();
+
+    // World distance along the ray to the next side crossed on each axis, and between crossings on each axis.
+    let mut next_crossing = Vec3::splat(f32::INFINITY);
+    let mut crossing_gap = Vec3::splat(f32::INFINITY);
+    for axis in 0..3 {
+        if grid_direction[axis] != 0.0 {
+            let boundary = position[axis] as f32 + 0.5 * step[axis] as f32;
+            next_crossing[axis] = (boundary - start[axis]) / grid_direction[axis];
+            crossing_gap[axis] = 1.0 / grid_direction[axis].abs();
+        }
+    }
+
+    loop {
+        let distance = next_crossing.min_element();
+        if distance > max_distance {
+            return None;
+        }
+
+        let axis = (0..3).find(|axis| next_crossing[*axis] == distance)?;
+        let side = TileType::from_axis(axis, step[axis]);
+        let next = position + side.direction();
+        let point = origin + direction * distance;
+
+        if map[position][side].is_some() {
+            return Some(RayHit { position, tile_type: side, point, distance });
+        }
+        // Past the edge of the map and heading further out, so there's nothing left to hit.
+        if (step[axis] < 0 && next[axis] < map_min[axis]) || (step[axis] > 0 && next[axis] > map_max[axis]) {
+            return None;
+        }
+        if map[next][side.opposite()].is_some() {
+            return Some(RayHit { position: next, tile_type: side.opposite(), point, distance });
+        }
+
+        position = next;
+        next_crossing[axis] += crossing_gap[axis];
+    }
+}
+
+// Data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub position: IVec3,
+    pub tile_type: TileType,
+    // Where the ray crossed the face, in world space.
+    pub point: Vec3,
+    pub distance: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{place_tile, TileData, TileKind, TileMaterial, CHUNK_SIZE};
+
+    // A wall on the east side of (4, 0, 1), looked at from the center of (1, 0, 1).
+    fn setup() -> (GridMap, MapScale, Vec3) {
+        let mut map = GridMap::new();
+        place_tile(&mut map, TileData::new(TileKind::Wall, TileMaterial::Concrete), TileType::East, IVec3::new(4, 0, 1));
+
+        let scale = MapScale::default();
+        let origin = scale.cell_to_world(IVec3::new(1, 0, 1));
+        (map, scale, origin)
+    }
+
+    #[test]
+    fn ray_hits_wall() {
+        let (map, scale, origin) = setup();
+        let hit = raycast(&map, &scale, origin, Vec3::X, 20.0).unwrap();
+
+        assert_eq!(hit.position, IVec3::new(4, 0, 1));
+        assert_eq!(hit.tile_type, TileType::East);
+        assert!((hit.point - scale.grid_to_world(Vec3::new(4.5, 0.0, 1.0))).length() < 1e-4);
+        assert!((hit.distance - 7.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ray_stops_at_max_distance() {
+        let (map, scale, origin) = setup();
+
+        assert_eq!(raycast(&map, &scale, origin, Vec3::X, 6.0), None);
+    }
+    #[test]
+    fn ray_crosses_unallocated_chunks() {
+        let mut map = GridMap::new();
+        let start = IVec3::new(1, 0, 1);
+        let wall = IVec3::new(CHUNK_SIZE * 2 + 8, 0, 1);
+        place_tile(&mut map, TileData::new(TileKind::Floor, TileMaterial::Concrete), TileType::Floor, start);
+        place_tile(&mut map, TileData::new(TileKind::Wall, TileMaterial::Concrete), TileType::East, wall);
+        assert!(map.position_oob(IVec3::new(CHUNK_SIZE + 8, 0, 1)));
+
+        let scale = MapScale::default();
+        let hit = raycast(&map, &scale, scale.cell_to_world(start), Vec3::X, 100.0).unwrap();
+
+        assert_eq!(hit.position, wall);
+        assert_eq!(hit.tile_type, TileType::East);
+    }
+}
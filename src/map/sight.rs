@@ -0,0 +1,149 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::*;
+
+use super::{GridMap, TileFlags, TileType};
+
+const SIDES: [TileType; 6] = [TileType::North, TileType::East, TileType::South, TileType::West, TileType::Ceiling, TileType::Floor];
+
+// Helper Functions
+// Every position visible from the origin within radius cells, origin included.
+// Flood fills through see-through sides first so only positions that could possibly be seen get a line of sight check.
+pub fn field_of_view (map: &GridMap, origin: IVec3, radius: i32) -> HashSet<IVec3> {
+    let mut visible = HashSet::new();
+    if map.position_oob(origin) {
+        return visible;
+    }
+
+    let mut reached = HashSet::from([origin]);
+    let mut queue = VecDeque::from([origin]);
+
+    while let Some(position) = queue.pop_front() {
+        if has_line_of_sight(map, origin, position) {
+            visible.insert(position);
+        }
+
+        for side in SIDES {
+            let next = position + side.direction();
+            if (next - origin).dot(next - origin) > radius * radius || map.position_oob(next) || reached.contains(&next) {
+                continue;
+            }
+
+            if !side_opaque(map, position, side) {
+                reached.insert(next);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    visible
+}
+
+// Whether a straight line between the centers of two positions crosses no opaque side.
+// Walks every position the line passes through (a 3D DDA). Where the line passes exactly
+// through an edge or corner it is let through if any order of crossing the tied sides is clear.
+pub fn has_line_of_sight (map: &GridMap, from: IVec3, to: IVec3) -> bool {
+    let delta = (to - from).as_vec3();
+    let step = (to - from).signum();
+
+    // How far along the line (0 to 1) the next side on each axis is crossed, and how far apart crossings on each axis are.
+    let mut next_crossing = Vec3::splat(f32::INFINITY);
+    let mut crossing_gap = Vec3::splat(f32::INFINITY);
+    for axis in 0..3 {
+        if step[axis] != 0 {
+            crossing_gap[axis] = 1.0 / delta[axis].abs();
+            next_crossing[axis] = 0.5 * crossing_gap[axis];
+        }
+    }
+
+    let mut position = from;
+    while position != to {
+        let nearest = next_crossing.min_element();
+        let tied = (0..3)
+            .filter(|axis| (next_crossing[*axis] - nearest).abs() < 1e-5)
+            .collect::<Vec<usize>>();
+
+        let clear = permutations(&tied).iter().any(|order| {
+            let mut current = position;
+            order.iter().all(|axis| {
+                let side = TileType::from_axis(*axis, step[*axis]);
+                let blocked = side_opaque(map, current, side);
+                current += side.direction();
+                !blocked
+            })
+        });
+
+        if !clear {
+            return false;
+        }
+
+        for axis in tied {
+            position[axis] += step[axis];
+            next_crossing[axis] += crossing_gap[axis];
+        }
+    }
+
+    true
+}
+
+// Whether something on either side of this side of a position blocks sight through it.
+// Closed doors aren't transparent, so they block sight like walls.
+pub fn side_opaque (map: &GridMap, position: IVec3, side: TileType) -> bool {
+    let next = position + side.direction();
+
+    let opaque = |cell: IVec3, face: TileType| {
+        !map.position_oob(cell) && map[cell][face]
+            .is_some_and(|data| !data.flags.contains(TileFlags::TRANSPARENT))
+    };
+
+    opaque(position, side) || opaque(next, side.opposite())
+}
+
+fn permutations (axes: &[usize]) -> Vec<Vec<usize>> {
+    match axes {
+        [a, b] => vec![vec![*a, *b], vec![*b, *a]],
+        [a, b, c] => vec![
+            vec![*a, *b, *c], vec![*a, *c, *b],
+            vec![*b, *a, *c], vec![*b, *c, *a],
+            vec![*c, *a, *b], vec![*c, *b, *a],
+        ],
+        _ => vec![axes.to_vec()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{place_tile, TileData, TileKind, TileMaterial};
+
+    // An open 9 by 9 room with a single wall on the east side of (4, 0, 2).
+    fn room() -> GridMap {
+        let mut map = GridMap::new();
+        for x in 0..=8 {
+            for z in 0..=8 {
+                place_tile(&mut map, TileData::new(TileKind::Floor, TileMaterial::Concrete), TileType::Floor, IVec3::new(x, 0, z));
+            }
+        }
+        place_tile(&mut map, TileData::new(TileKind::Wall, TileMaterial::Concrete), TileType::East, IVec3::new(4, 0, 2));
+        map
+    }
+
+    #[test]
+    fn field_of_view_stops_at_walls() {
+        let map = room();
+        let visible = field_of_view(&map, IVec3::new(2, 0, 2), 8);
+
+        assert!(visible.contains(&IVec3::new(2, 0, 2)));
+        assert!(visible.contains(&IVec3::new(4, 0, 2)));
+        assert!(visible.contains(&IVec3::new(2, 0, 6)));
+        assert!(!visible.contains(&IVec3::new(6, 0, 2)));
+    }
+
+    #[test]
+    fn wall_blocks_line_of_sight() {
+        let map = room();
+
+        assert!(has_line_of_sight(&map, IVec3::new(1, 0, 4), IVec3::new(7, 0, 4)));
+        assert!(!has_line_of_sight(&map, IVec3::new(1, 0, 2), IVec3::new(7, 0, 2)));
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{digging::Debris, movement::CharacterController, player::Player};
+
+use super::{GridMap, MapScale, chunk_of};
+
+// Distances are in chunks, measured along the furthest axis.
+// Chunks are unloaded a little further out than they're loaded so walking along a border doesn't thrash.
+const LOAD_DISTANCE: i32 = 2;
+const UNLOAD_DISTANCE: i32 = 3;
+
+// Systems
+// Unloads chunks that are far from the player and every other actor, and reloads them when one comes back.
+// Chunks are kept around actors as well as the player, since an actor in an unloaded chunk has no floor to stand on.
+// Tile data stays in the map the whole time, only the chunk's meshes and colliders are despawned,
+// so marking the chunk dirty is enough for the mesher to do either.
+// Debris in an unloaded chunk is despawned as well, it would only fall through the missing colliders.
+// Tile entities (GridMap::entity) are left alone, since they can't be rebuilt from tile data.
+pub fn stream_chunks (
+    mut map: ResMut<GridMap>,
+    map_scale: Res<MapScale>,
+    mut unloaded: ResMut<UnloadedChunks>,
+
+    actor_query: Query<&Transform, Or<(With<Player>, With<CharacterController>)>>,
+    debris_query: Query<(Entity, &Transform), With<Debris>>,
+
+    mut commands: Commands,
+) {
+    let actor_chunks = actor_query.iter()
+        .map(|transform| chunk_of(map_scale.world_to_cell(transform.translation)))
+        .collect::<HashSet<IVec3>>();
+
+    if actor_chunks.is_empty() {
+        return;
+    }
+
+    // To the nearest actor.
+    let distance = |chunk: IVec3| actor_chunks.iter()
+        .map(|actor_chunk| (chunk - *actor_chunk).abs().max_element())
+        .min()
+        .unwrap_or(i32::MAX);
+
+    let changed = map.allocated_chunks()
+        .filter(|chunk| {
+            if unloaded.contains(chunk) {
+                distance(*chunk) <= LOAD_DISTANCE
+            } else {
+                distance(*chunk) > UNLOAD_DISTANCE
+            }
+        })
+        .collect::<Vec<IVec3>>();
+
+    for chunk in changed {
+        if !unloaded.remove(&chunk) {
+            unloaded.insert(chunk);
+        }
+        map.mark_dirty(chunk);
+    }
+
+    for (entity, transform) in debris_query.iter() {
+        if unloaded.contains(&chunk_of(map_scale.world_to_cell(transform.translation))) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Resources
+// Chunks whose meshes and colliders have been despawned because no actor is near.
+#[derive(Default, Deref, DerefMut)]
+pub struct UnloadedChunks (HashSet<IVec3>);
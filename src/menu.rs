@@ -0,0 +1,410 @@
+use bevy::{prelude::*, app::AppExit};
+use heron::PhysicsTime;
+use iyes_loopless::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{assets::FontAssets, automap::{FullAutomap, Minimap}, camera::LookSettings, controls::{MetaAction, Rebinding}, digging::{Debris, TileDamage}, map::MapRng, player::Player, GameState};
+
+const PANEL_COLOR: Color = Color::rgba(0.02, 0.02, 0.03, 0.9);
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const BUTTON_HOVER_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+const TEXT_COLOR: Color = Color::WHITE;
+const TITLE_SIZE: f32 = 40.0;
+const FONT_SIZE: f32 = 22.0;
+
+const MAX_SEED_LENGTH: usize = 20;
+
+//Plugin
+#[derive(Default)]
+pub struct MenuPlugin;
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<GameInProgress>()
+            .init_resource::<SeedEntry>()
+            .init_resource::<SettingsReturn>();
+    }
+}
+
+// Systems
+pub fn spawn_ui_camera (
+    mut commands: Commands,
+) {
+    commands.spawn_bundle(UiCameraBundle::default());
+}
+
+pub fn spawn_main_menu (
+    fonts: Res<FontAssets>,
+    in_progress: Res<GameInProgress>,
+    seed: Res<SeedEntry>,
+
+    mut commands: Commands,
+) {
+    spawn_menu(&mut commands, &fonts, "CryptHack", |parent, style| {
+        parent
+            .spawn_bundle(TextBundle {
+                text: Text::with_section(seed.label(), style.clone(), default()),
+                style: Style { margin: Rect::all(Val::Px(8.0)), ..default() },
+                ..default()
+            })
+            .insert(SeedText);
+
+        spawn_button(parent, style, "New Game", MenuButton::NewGame);
+        if **in_progress {
+            spawn_button(parent, style, "Continue", MenuButton::Continue);
+        }
+        spawn_button(parent, style, "Settings", MenuButton::Settings);
+        spawn_button(parent, style, "Quit", MenuButton::Quit);
+    });
+}
+
+pub fn spawn_pause_menu (
+    fonts: Res<FontAssets>,
+
+    mut commands: Commands,
+) {
+    spawn_menu(&mut commands, &fonts, "Paused", |parent, style| {
+        spawn_button(parent, style, "Resume", MenuButton::Resume);
+        spawn_button(parent, style, "Settings", MenuButton::Settings);
+        spawn_button(parent, style, "Main Menu", MenuButton::MainMenu);
+        spawn_button(parent, style, "Quit", MenuButton::Quit);
+    });
+}
+
+pub fn spawn_settings_menu (
+    fonts: Res<FontAssets>,
+    settings: Res<LookSettings>,
+
+    mut commands: Commands,
+) {
+    spawn_menu(&mut commands, &fonts, "Settings", |parent, style| {
+        for row in [SettingRow::Sensitivity, SettingRow::InvertY, SettingRow::FieldOfView, SettingRow::Smoothing] {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style { align_items: AlignItems::Center, ..default() },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|line| {
+                    if row != SettingRow::InvertY {
+                        spawn_button(line, style, "-", MenuButton::Adjust(row, -1.0));
+                    }
+
+                    line
+                        .spawn_bundle(TextBundle {
+                            text: Text::with_section(row.describe(&settings), style.clone(), default()),
+                            style: Style { margin: Rect::all(Val::Px(8.0)), ..default() },
+                            ..default()
+                        })
+                        .insert(SettingLabel(row));
+
+                    spawn_button(line, style, if row == SettingRow::InvertY { "Toggle" } else { "+" }, MenuButton::Adjust(row, 1.0));
+                });
+        }
+
+        parent.spawn_bundle(TextBundle {
+            text: Text::with_section("Controls can be rebound in game with F1", style.clone(), default()),
+            style: Style { margin: Rect::all(Val::Px(8.0)), ..default() },
+            ..default()
+        });
+
+        spawn_button(parent, style, "Back", MenuButton::Back);
+    });
+}
+
+pub fn despawn_menus (
+    menu_query: Query<Entity, With<MenuRoot>>,
+
+    mut commands: Commands,
+) {
+    for entity in menu_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Stops physics and frees the cursor whenever a menu is up. Gameplay systems only run while playing,
+// so they're already stopped.
+pub fn freeze_game (
+    mut physics_time: ResMut<PhysicsTime>,
+    mut windows: ResMut<Windows>,
+) {
+    physics_time.pause();
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(false);
+        window.set_cursor_visibility(true);
+    }
+}
+
+pub fn resume_game (
+    mut physics_time: ResMut<PhysicsTime>,
+    mut in_progress: ResMut<GameInProgress>,
+) {
+    physics_time.resume();
+    **in_progress = true;
+}
+
+// Despawns the player and everything else left over from the last game. The map clears itself.
+pub fn despawn_game (
+    mut damage: ResMut<TileDamage>,
+
+    game_query: Query<Entity, Or<(With<Player>, With<Debris>, With<Minimap>, With<FullAutomap>)>>,
+
+    mut commands: Commands,
+) {
+    for entity in game_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    damage.clear();
+}
+
+// Pause pauses and unpauses the game, and backs out of the settings screen.
+pub fn toggle_pause (
+    state: Res<CurrentState<GameState>>,
+    rebinding: Res<Rebinding>,
+    settings_return: Res<SettingsReturn>,
+
+    meta_query: Query<&ActionState<MetaAction>>,
+
+    mut commands: Commands,
+) {
+    if rebinding.open() || !meta_query.iter().any(|action_state| action_state.just_pressed(MetaAction::Pause)) {
+        return;
+    }
+
+    match state.0 {
+        GameState::Playing => commands.insert_resource(NextState(GameState::Paused)),
+        GameState::Paused => commands.insert_resource(NextState(GameState::Playing)),
+        GameState::Settings => commands.insert_resource(NextState((**settings_return).clone().unwrap_or(GameState::MainMenu))),
+        _ => {}
+    }
+}
+
+pub fn menu_buttons (
+    state: Res<CurrentState<GameState>>,
+    seed: Res<SeedEntry>,
+    mut settings_return: ResMut<SettingsReturn>,
+    mut settings: ResMut<LookSettings>,
+
+    mut ev_exit: EventWriter<AppExit>,
+
+    mut button_query: Query<(&Interaction, &MenuButton, &mut UiColor), Changed<Interaction>>,
+
+    mut commands: Commands,
+) {
+    for (interaction, button, mut color) in button_query.iter_mut() {
+        match interaction {
+            Interaction::Clicked => {}
+            Interaction::Hovered => {
+                *color = BUTTON_HOVER_COLOR.into();
+                continue;
+            }
+            Interaction::None => {
+                *color = BUTTON_COLOR.into();
+                continue;
+            }
+        }
+
+        match button {
+            MenuButton::NewGame => {
+                let seed = seed.seed();
+                info!("Starting a new game with seed {}", seed);
+
+                commands.insert_resource(MapRng::new(seed));
+                commands.insert_resource(NextState(GameState::StartMapGen));
+            }
+            MenuButton::Continue | MenuButton::Resume => commands.insert_resource(NextState(GameState::Playing)),
+            MenuButton::Settings => {
+                **settings_return = Some(state.0.clone());
+                commands.insert_resource(NextState(GameState::Settings));
+            }
+            MenuButton::MainMenu => commands.insert_resource(NextState(GameState::MainMenu)),
+            MenuButton::Back => commands.insert_resource(NextState(settings_return.take().unwrap_or(GameState::MainMenu))),
+            MenuButton::Quit => ev_exit.send(AppExit),
+            MenuButton::Adjust(row, amount) => row.adjust(&mut settings, *amount),
+        }
+    }
+}
+
+// Typing on the main menu enters a seed. Backspace removes the last character.
+pub fn seed_input (
+    key: Res<Input<KeyCode>>,
+    mut seed: ResMut<SeedEntry>,
+    mut ev_character: EventReader<ReceivedCharacter>,
+
+    mut text_query: Query<&mut Text, With<SeedText>>,
+) {
+    for ev in ev_character.iter() {
+        if ev.char.is_ascii_alphanumeric() && seed.len() < MAX_SEED_LENGTH {
+            seed.push(ev.char);
+        }
+    }
+
+    if key.just_pressed(KeyCode::Back) {
+        seed.pop();
+    }
+
+    if seed.is_changed() {
+        for mut text in text_query.iter_mut() {
+            text.sections[0].value = seed.label();
+        }
+    }
+}
+
+pub fn update_setting_labels (
+    settings: Res<LookSettings>,
+
+    mut label_query: Query<(&SettingLabel, &mut Text)>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for (label, mut text) in label_query.iter_mut() {
+        text.sections[0].value = label.0.describe(&settings);
+    }
+}
+
+pub fn save_settings (
+    settings: Res<LookSettings>,
+) {
+    settings.save();
+}
+
+// Helper Functions
+// 64 bit FNV-1a. Word seeds have to hash the same on every build, which std's hashers don't promise.
+fn fnv1a (bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+// A full screen panel with a title, and whatever build adds below it.
+fn spawn_menu (commands: &mut Commands, fonts: &FontAssets, title: &str, build: impl FnOnce(&mut ChildBuilder, &TextStyle)) {
+    let style = TextStyle { font: fonts.ui.clone(), font_size: FONT_SIZE, color: TEXT_COLOR };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: PANEL_COLOR.into(),
+            ..default()
+        })
+        .insert(MenuRoot)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(title, TextStyle { font_size: TITLE_SIZE, ..style.clone() }, default()),
+                style: Style { margin: Rect::all(Val::Px(20.0)), ..default() },
+                ..default()
+            });
+
+            build(parent, &style);
+        });
+}
+
+fn spawn_button (parent: &mut ChildBuilder, style: &TextStyle, label: &str, button: MenuButton) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                margin: Rect::all(Val::Px(4.0)),
+                padding: Rect::all(Val::Px(8.0)),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: BUTTON_COLOR.into(),
+            ..default()
+        })
+        .insert(button)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(label, style.clone(), default()),
+                ..default()
+            });
+        });
+}
+
+// Components
+#[derive(Component)]
+pub struct MenuRoot;
+
+#[derive(Component)]
+pub struct SeedText;
+
+#[derive(Component)]
+pub struct SettingLabel (SettingRow);
+
+#[derive(Component, Debug, Clone, Copy)]
+pub enum MenuButton {
+    NewGame,
+    Continue,
+    Settings,
+    Quit,
+    Resume,
+    MainMenu,
+    Back,
+    // Steps a setting up (positive) or down (negative).
+    Adjust(SettingRow, f32),
+}
+
+// Resources
+// Set once a game has been started, so the main menu can offer to continue it.
+#[derive(Default, Deref, DerefMut)]
+pub struct GameInProgress (bool);
+
+// The seed typed on the main menu. Empty picks a random one.
+#[derive(Default, Deref, DerefMut)]
+pub struct SeedEntry (String);
+impl SeedEntry {
+    // Numbers are used as they are, anything else is hashed so words work as seeds too.
+    pub fn seed(&self) -> u64 {
+        if self.is_empty() {
+            return rand::random();
+        }
+
+        self.parse().unwrap_or_else(|_| fnv1a(self.as_bytes()))
+    }
+
+    pub fn label(&self) -> String {
+        if self.is_empty() {
+            "Seed: (random, type to enter one)".to_string()
+        } else {
+            format!("Seed: {}", self.0)
+        }
+    }
+}
+
+// The menu the settings screen goes back to.
+#[derive(Default, Deref, DerefMut)]
+pub struct SettingsReturn (Option<GameState>);
+
+// Data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingRow {
+    Sensitivity,
+    InvertY,
+    FieldOfView,
+    Smoothing,
+}
+impl SettingRow {
+    pub fn describe(&self, settings: &LookSettings) -> String {
+        match self {
+            SettingRow::Sensitivity => format!("Sensitivity: {:.2}", settings.sensitivity),
+            SettingRow::InvertY => format!("Invert Y: {}", if settings.invert_y { "On" } else { "Off" }),
+            SettingRow::FieldOfView => format!("Field of view: {:.0}", settings.fov),
+            SettingRow::Smoothing => format!("Smoothing: {:.2}s", settings.smoothing),
+        }
+    }
+
+    pub fn adjust(&self, settings: &mut LookSettings, steps: f32) {
+        match self {
+            SettingRow::Sensitivity => settings.sensitivity = (settings.sensitivity + steps * 0.05).clamp(0.05, 2.0),
+            SettingRow::InvertY => settings.invert_y = !settings.invert_y,
+            SettingRow::FieldOfView => settings.fov = (settings.fov + steps * 5.0).clamp(50.0, 110.0),
+            SettingRow::Smoothing => settings.smoothing = (settings.smoothing + steps * 0.02).clamp(0.0, 0.2),
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use bevy::prelude::*;
+use heron::{prelude::*, rapier_plugin::{PhysicsWorld, ShapeCastCollisionType}};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{actions::Action, controls::GamepadSticks, map::{GridMap, MapScale, TileData, TileFlags, TileMaterial, TileType}};
+
+// How far below the bottom of an actor's shape the ground can be and still count.
+const GROUND_TOLERANCE: f32 = 0.05;
+// How quickly the camera moves between standing and crouching heights, in units per second.
+const CROUCH_CAMERA_SPEED: f32 = 2.0;
+// Actors moving up faster than this have just jumped and aren't grounded yet, even if the rays still reach the floor.
+const MAX_GROUNDED_RISE: f32 = 0.5;
+// How much faster sprinting is than walking.
+const SPRINT_MULTIPLIER: f32 = 1.6;
+// Once stamina runs out, sprinting stays off until it has regenerated this fraction of its maximum.
+const EXHAUSTION_RECOVERY: f32 = 0.25;
+// Character controllers stop this far short of whatever they run into, so they never start a move already touching it.
+const SKIN_WIDTH: f32 = 0.01;
+// The tallest ledge an actor should step up, in cells. Every real change of level in the map is a whole cell,
+// taken by stairs or a jump, so stepping only has to carry actors over seams and small lips between tile colliders.
+// A tenth of a cell does that while staying far too low to get over anything the map means to block.
+const STEP_CELLS: f32 = 0.1;
+// How many times a move can be redirected along the surfaces it hits in one frame.
+const MAX_SLIDES: usize = 4;
+
+// Systems
+// Casts rays down from an actor's center and edges to see if it's standing on something,
+// inserting or removing Grounded to match.
+pub fn detect_ground (
+    physics_world: PhysicsWorld,
+
+    mut jump_query: Query<(Entity, &Transform, &CollisionShape, Option<&Velocity>, Option<&CharacterController>, &mut Jump, Option<&Grounded>)>,
+
+    mut commands: Commands,
+) {
+    for (entity, transform, shape, velocity, controller, mut jump, grounded) in jump_query.iter_mut() {
+        let (radius, bottom) = match shape {
+            CollisionShape::Capsule { radius, half_segment } => (*radius, half_segment + radius),
+            CollisionShape::Sphere { radius } => (*radius, *radius),
+            CollisionShape::Cuboid { half_extends, .. } => (half_extends.x.min(half_extends.z), half_extends.y),
+            _ => continue,
+        };
+
+        let rise = controller.map(|controller| controller.velocity.y)
+            .or_else(|| velocity.map(|velocity| velocity.linear.y))
+            .unwrap_or(0.0);
+
+        let ray = Vec3::new(0.0, -(bottom + GROUND_TOLERANCE), 0.0);
+        let on_ground = rise <= MAX_GROUNDED_RISE
+            && probe(&physics_world, entity, transform.translation, radius, ray);
+
+        if on_ground {
+            jump.since_grounded = 0.0;
+            if grounded.is_none() {
+                commands.entity(entity).insert(Grounded);
+            }
+        } else if grounded.is_some() {
+            commands.entity(entity).remove::<Grounded>();
+        }
+    }
+}
+
+// Shrinks an actor's capsule while crouch is held and lowers its camera.
+// Standing back up waits until there's room overhead.
+pub fn crouch (
+    time: Res<Time>,
+    physics_world: PhysicsWorld,
+
+    mut crouch_query: Query<(Entity, &ActionState<Action>, &Children, &mut Crouch, &mut CollisionShape, &mut Transform), Without<Camera>>,
+    mut camera_query: Query<&mut Transform, With<Camera>>,
+) {
+    for (entity, action_state, children, mut crouch, mut shape, mut transform) in crouch_query.iter_mut() {
+        let (radius, half_segment) = match shape.as_ref() {
+            CollisionShape::Capsule { radius, half_segment } => (*radius, *half_segment),
+            _ => continue,
+        };
+
+        // Feet stay where they are, so the center moves by how much the capsule shrinks or grows at each end.
+        let shift = crouch.standing_half_segment - crouch.crouching_half_segment;
+
+        if action_state.pressed(Action::Crouch) && !crouch.crouched {
+            crouch.crouched = true;
+            transform.translation.y -= shift;
+            *shape = CollisionShape::Capsule { radius, half_segment: crouch.crouching_half_segment };
+        }
+        else if !action_state.pressed(Action::Crouch) && crouch.crouched {
+            let ray = Vec3::new(0.0, half_segment + radius + shift * 2.0, 0.0);
+            if !probe(&physics_world, entity, transform.translation, radius, ray) {
+                crouch.crouched = false;
+                transform.translation.y += shift;
+                *shape = CollisionShape::Capsule { radius, half_segment: crouch.standing_half_segment };
+            }
+        }
+
+        let camera_height = if crouch.crouched { crouch.crouching_camera_height } else { crouch.standing_camera_height };
+        for child in children.iter() {
+            if let Ok(mut camera_transform) = camera_query.get_mut(*child) {
+                let offset = camera_height - camera_transform.translation.y;
+                let step = CROUCH_CAMERA_SPEED * time.delta_seconds();
+                camera_transform.translation.y += offset.clamp(-step, step);
+            }
+        }
+    }
+}
+
+// Moves kinematic characters by their controller's velocity, sliding along whatever they hit.
+// Grounded characters step up onto ledges no taller than their step height and stay stuck to
+// the ground when walking down stairs and slopes, instead of launching off them.
+pub fn move_characters (
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    physics_world: PhysicsWorld,
+
+    mut controller_query: Query<(Entity, &CollisionShape, &mut Transform, &mut CharacterController, Option<&Grounded>)>,
+) {
+    let delta = time.delta_seconds();
+    if delta <= 0.0 {
+        return;
+    }
+
+    for (entity, shape, mut transform, mut controller, grounded) in controller_query.iter_mut() {
+        let grounded = grounded.is_some();
+
+        let horizontal = Vec3::new(controller.velocity.x, 0.0, controller.velocity.z);
+        let wish = Vec3::new(controller.wish.x, 0.0, controller.wish.z);
+        let horizontal = if wish != Vec3::ZERO {
+            let acceleration = if grounded { controller.acceleration } else { controller.acceleration * controller.air_control };
+            move_towards(horizontal, wish, acceleration * delta)
+        } else if grounded {
+            move_towards(horizontal, Vec3::ZERO, controller.friction * delta)
+        } else {
+            horizontal
+        };
+        controller.velocity.x = horizontal.x;
+        controller.velocity.z = horizontal.z;
+
+        if grounded && controller.velocity.y <= 0.0 {
+            controller.velocity.y = 0.0;
+        } else {
+            controller.velocity.y += Vec3::from(*gravity).y * delta;
+        }
+
+        let caster = ShapeCaster { physics_world: &physics_world, entity, shape, rotation: transform.rotation };
+        let walkable = controller.max_slope.to_radians().cos();
+
+        let start = transform.translation;
+        let motion = controller.velocity * delta;
+        let (mut position, mut normals) = caster.slide(start, motion, grounded, walkable);
+
+        // Whatever stopped us might be short enough to step over. Lift, move, and drop back down,
+        // keeping the result only if it lands on walkable ground further along than we got without it.
+        let flat_motion = Vec3::new(motion.x, 0.0, motion.z);
+        let progress = |end: Vec3| Vec3::new(end.x - start.x, 0.0, end.z - start.z).length();
+        if grounded && controller.step_height > 0.0 && flat_motion.length() - progress(position) > SKIN_WIDTH {
+            let lifted = caster.cast(start, Vec3::Y * controller.step_height)
+                .map_or(start + Vec3::Y * controller.step_height, |(position, _)| position);
+            let (stepped, step_normals) = caster.slide(lifted, flat_motion, grounded, walkable);
+
+            let drop = Vec3::Y * -(lifted.y - start.y + SKIN_WIDTH * 2.0);
+            if let Some((landed, normal)) = caster.cast(stepped, drop) {
+                if normal.y >= walkable && progress(landed) > progress(position) + SKIN_WIDTH {
+                    position = landed;
+                    normals = step_normals;
+                }
+            }
+        }
+
+        if grounded && controller.velocity.y <= 0.0 {
+            if let Some((snapped, normal)) = caster.cast(position, Vec3::Y * -controller.step_height) {
+                if normal.y >= walkable {
+                    position = snapped;
+                }
+            }
+        }
+
+        // Lose whatever velocity went into the surfaces we hit, so we slide along walls and stop on floors and ceilings.
+        for normal in normals {
+            let into = controller.velocity.dot(normal);
+            if into < 0.0 {
+                controller.velocity -= normal * into;
+            }
+        }
+
+        transform.translation = position;
+    }
+}
+
+// Speeds up actors while sprint is held, draining their stamina. Stamina regenerates
+// after a short delay once they stop, and running out of it stops them sprinting until it's partly back.
+pub fn sprint (
+    time: Res<Time>,
+    sticks: Res<GamepadSticks>,
+
+    mut sprint_query: Query<(&ActionState<Action>, &mut Stamina, &mut MovementModifiers, Option<&Crouch>)>,
+) {
+    for (action_state, mut stamina, mut modifiers, crouch) in sprint_query.iter_mut() {
+        let moving = [Action::WalkForward, Action::WalkBackward, Action::StrafeLeft, Action::StrafeRight]
+            .into_iter()
+            .any(|action| action_state.pressed(action))
+            || sticks.left != Vec2::ZERO;
+        let crouched = crouch.is_some_and(|crouch| crouch.crouched);
+
+        let sprinting = action_state.pressed(Action::Sprint) && moving && !crouched && !stamina.exhausted;
+
+        if sprinting {
+            stamina.current = (stamina.current - stamina.drain * time.delta_seconds()).max(0.0);
+            stamina.regen_delay.reset();
+            if stamina.current <= 0.0 {
+                stamina.exhausted = true;
+            }
+            modifiers.set(ModifierSource::Sprint, SPRINT_MULTIPLIER);
+        } else {
+            if stamina.regen_delay.tick(time.delta()).finished() {
+                stamina.current = (stamina.current + stamina.regen * time.delta_seconds()).min(stamina.max);
+            }
+            if stamina.exhausted && stamina.current >= stamina.max * EXHAUSTION_RECOVERY {
+                stamina.exhausted = false;
+            }
+            modifiers.remove(ModifierSource::Sprint);
+        }
+    }
+}
+
+// Slows actors down depending on what they're standing on.
+pub fn terrain_modifiers (
+    map: Res<GridMap>,
+    map_scale: Res<MapScale>,
+
+    mut modifier_query: Query<(&Transform, &mut MovementModifiers)>,
+) {
+    for (transform, mut modifiers) in modifier_query.iter_mut() {
+        let position = map_scale.world_to_cell(transform.translation);
+        match map[position][TileType::Floor].map(terrain_multiplier) {
+            Some(multiplier) if multiplier != 1.0 => modifiers.set(ModifierSource::Terrain, multiplier),
+            _ => modifiers.remove(ModifierSource::Terrain),
+        }
+    }
+}
+
+// Counts down timed modifiers and drops the ones that ran out.
+pub fn tick_modifiers (
+    time: Res<Time>,
+
+    mut modifier_query: Query<&mut MovementModifiers>,
+) {
+    for mut modifiers in modifier_query.iter_mut() {
+        for modifier in modifiers.iter_mut() {
+            if let Some(duration) = &mut modifier.duration {
+                duration.tick(time.delta());
+            }
+        }
+
+        modifiers.retain(|modifier| modifier.duration.as_ref().is_none_or(|duration| !duration.finished()));
+    }
+}
+
+// Helper Functions
+// How fast actors can move over a floor, compared to bare stone.
+pub fn terrain_multiplier (data: TileData) -> f32 {
+    if data.flags.contains(TileFlags::LIQUID) {
+        return 0.5;
+    }
+
+    match data.material {
+        TileMaterial::Grass => 0.95,
+        TileMaterial::CarpetRed => 0.9,
+        _ => 1.0,
+    }
+}
+
+fn move_towards (current: Vec3, target: Vec3, max_step: f32) -> Vec3 {
+    let offset = target - current;
+    if offset.length() <= max_step {
+        target
+    } else {
+        current + offset.normalize() * max_step
+    }
+}
+
+// Casts a ray from an actor's center and four points around it, ignoring the actor itself.
+// True if any of them hit something.
+fn probe (physics_world: &PhysicsWorld, entity: Entity, center: Vec3, radius: f32, ray: Vec3) -> bool {
+    let inset = radius * 0.7;
+    [
+        Vec3::ZERO,
+        Vec3::new(inset, 0.0, 0.0),
+        Vec3::new(-inset, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, inset),
+        Vec3::new(0.0, 0.0, -inset),
+    ]
+    .iter()
+    .any(|offset| {
+        physics_world
+            .ray_cast_with_filter(center + *offset, ray, true, CollisionLayers::default(), |hit| hit != entity)
+            .is_some()
+    })
+}
+
+// Components
+// On an actor that's standing on something.
+#[derive(Component, Default, Debug, Clone, Copy)]
+pub struct Grounded;
+
+// Lets an actor jump a set height while grounded.
+// Coyote time still allows a jump shortly after walking off a ledge,
+// and buffering remembers a jump pressed shortly before landing.
+#[derive(Component, Debug, Clone)]
+pub struct Jump {
+    // How high a jump goes, in world units.
+    pub height: f32,
+    pub coyote_time: f32,
+    pub buffer_time: f32,
+    // Seconds since the actor was last grounded and since jump was last pressed.
+    pub since_grounded: f32,
+    pub since_pressed: f32,
+}
+impl Default for Jump {
+    fn default() -> Self {
+        Jump {
+            height: 1.2,
+            coyote_time: 0.1,
+            buffer_time: 0.15,
+            since_grounded: f32::INFINITY,
+            since_pressed: f32::INFINITY,
+        }
+    }
+}
+impl Jump {
+    pub fn tick(&mut self, delta: f32) {
+        self.since_grounded += delta;
+        self.since_pressed += delta;
+    }
+
+    pub fn press(&mut self) {
+        self.since_pressed = 0.0;
+    }
+
+    // Consumes a buffered press if the actor is, or very recently was, on the ground.
+    pub fn try_jump(&mut self) -> bool {
+        if self.since_pressed > self.buffer_time || self.since_grounded > self.coyote_time {
+            return false;
+        }
+
+        self.since_pressed = f32::INFINITY;
+        self.since_grounded = f32::INFINITY;
+        true
+    }
+
+    // The upward speed needed to reach height under the given gravity.
+    pub fn velocity(&self, gravity: f32) -> f32 {
+        (2.0 * gravity.abs() * self.height).sqrt()
+    }
+}
+
+// Moves an actor as a kinematic body instead of leaving it to the physics engine.
+// Other systems set wish to where the actor wants to go and may change velocity directly, to jump for example.
+#[derive(Component, Debug, Clone)]
+pub struct CharacterController {
+    pub velocity: Vec3,
+    // The horizontal velocity the actor is trying to reach.
+    pub wish: Vec3,
+    // Top walking speed, in units per second.
+    pub max_speed: f32,
+    // How quickly the actor speeds up towards wish and slows down without it on the ground, in units per second squared.
+    pub acceleration: f32,
+    pub friction: f32,
+    // The fraction of acceleration the actor has in the air.
+    pub air_control: f32,
+    // The tallest ledge the actor can walk up without jumping.
+    pub step_height: f32,
+    // The steepest slope the actor can walk up, in degrees.
+    pub max_slope: f32,
+}
+impl Default for CharacterController {
+    fn default() -> Self {
+        CharacterController {
+            velocity: Vec3::ZERO,
+            wish: Vec3::ZERO,
+            max_speed: 6.0,
+            acceleration: 30.0,
+            friction: 25.0,
+            air_control: 0.3,
+            // STEP_CELLS at the default map scale. for_scale works it out for others.
+            step_height: STEP_CELLS * MapScale::default().y + SKIN_WIDTH,
+            max_slope: 45.0,
+        }
+    }
+}
+impl CharacterController {
+    // Steps are measured in cells, so they have to grow with the map.
+    pub fn for_scale(scale: &MapScale) -> CharacterController {
+        CharacterController {
+            step_height: STEP_CELLS * scale.y + SKIN_WIDTH,
+            ..default()
+        }
+    }
+}
+
+// How long an actor can sprint for. Drain and regen are per second.
+#[derive(Component, Debug, Clone)]
+pub struct Stamina {
+    pub current: f32,
+    pub max: f32,
+    pub drain: f32,
+    pub regen: f32,
+    // How long after sprinting stops before stamina starts coming back.
+    pub regen_delay: Timer,
+    pub exhausted: bool,
+}
+impl Default for Stamina {
+    fn default() -> Self {
+        Stamina {
+            current: 100.0,
+            max: 100.0,
+            drain: 25.0,
+            regen: 15.0,
+            regen_delay: Timer::from_seconds(1.0, false),
+            exhausted: false,
+        }
+    }
+}
+
+// Everything currently speeding an actor up or slowing it down. Multipliers stack by multiplying together.
+#[derive(Component, Default, Debug, Clone, Deref, DerefMut)]
+pub struct MovementModifiers (Vec<MovementModifier>);
+impl MovementModifiers {
+    // Adds a modifier, replacing any other from the same source.
+    pub fn set(&mut self, source: ModifierSource, multiplier: f32) {
+        self.remove(source);
+        self.0.push(MovementModifier { source, multiplier, duration: None });
+    }
+
+    // Adds a modifier that removes itself after some seconds.
+    pub fn set_timed(&mut self, source: ModifierSource, multiplier: f32, seconds: f32) {
+        self.remove(source);
+        self.0.push(MovementModifier { source, multiplier, duration: Some(Timer::from_seconds(seconds, false)) });
+    }
+
+    pub fn remove(&mut self, source: ModifierSource) {
+        self.0.retain(|modifier| modifier.source != source);
+    }
+
+    pub fn multiplier(&self) -> f32 {
+        self.0.iter().map(|modifier| modifier.multiplier).product()
+    }
+}
+
+// Lets an actor crouch to fit under low ceilings, at the cost of moving slower.
+// Heights are capsule half segments and camera offsets from the actor's center.
+#[derive(Component, Debug, Clone)]
+pub struct Crouch {
+    pub standing_half_segment: f32,
+    pub crouching_half_segment: f32,
+    pub standing_camera_height: f32,
+    pub crouching_camera_height: f32,
+    // What movement speed is multiplied by while crouched.
+    pub speed_multiplier: f32,
+    pub crouched: bool,
+}
+impl Crouch {
+    pub fn new(standing_half_segment: f32, crouching_half_segment: f32, standing_camera_height: f32, crouching_camera_height: f32) -> Crouch {
+        Crouch {
+            standing_half_segment,
+            crouching_half_segment,
+            standing_camera_height,
+            crouching_camera_height,
+            speed_multiplier: 0.5,
+            crouched: false,
+        }
+    }
+
+    pub fn speed_multiplier(&self) -> f32 {
+        if self.crouched { self.speed_multiplier } else { 1.0 }
+    }
+}
+
+// Data
+// Casts an actor's shape through the physics world, ignoring the actor itself.
+struct ShapeCaster<'a, 'w, 's> {
+    physics_world: &'a PhysicsWorld<'w, 's>,
+    entity: Entity,
+    shape: &'a CollisionShape,
+    rotation: Quat,
+}
+impl<'a, 'w, 's> ShapeCaster<'a, 'w, 's> {
+    // Where the shape stops when moved by motion from a position, and the normal of what stopped it.
+    // None if nothing is in the way, or if the shape started inside something and should be let out.
+    fn cast(&self, from: Vec3, motion: Vec3) -> Option<(Vec3, Vec3)> {
+        if motion.length_squared() < f32::EPSILON {
+            return None;
+        }
+
+        let entity = self.entity;
+        match self.physics_world.shape_cast_with_filter(self.shape, from, self.rotation, motion, CollisionLayers::default(), |hit| hit != entity).map(|hit| hit.collision_type) {
+            Some(ShapeCastCollisionType::Collided(info)) => {
+                let travelled = info.self_end_position - from;
+                let position = if travelled.length() > SKIN_WIDTH {
+                    info.self_end_position - motion.normalize() * SKIN_WIDTH
+                } else {
+                    from
+                };
+                Some((position, info.other_normal.normalize_or_zero()))
+            }
+            _ => None,
+        }
+    }
+
+    // Moves the shape as far as it can, redirecting what's left of the motion along every surface it hits.
+    // Slopes too steep to walk are treated as walls while grounded so they can't be climbed.
+    fn slide(&self, from: Vec3, motion: Vec3, grounded: bool, walkable: f32) -> (Vec3, Vec<Vec3>) {
+        let mut position = from;
+        let mut remaining = motion;
+        let mut normals = Vec::new();
+
+        for _ in 0..MAX_SLIDES {
+            let (hit_position, normal) = match self.cast(position, remaining) {
+                Some(hit) => hit,
+                None => {
+                    position += remaining;
+                    break;
+                }
+            };
+
+            let normal = if grounded && normal.y > 0.0 && normal.y < walkable {
+                Vec3::new(normal.x, 0.0, normal.z).normalize_or_zero()
+            } else {
+                normal
+            };
+
+            remaining -= hit_position - position;
+            remaining -= normal * remaining.dot(normal).min(0.0);
+            position = hit_position;
+            normals.push(normal);
+        }
+
+        (position, normals)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MovementModifier {
+    pub source: ModifierSource,
+    pub multiplier: f32,
+    // None lasts until removed.
+    pub duration: Option<Timer>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModifierSource {
+    Sprint,
+    Terrain,
+}
@@ -1,4 +1,7 @@
-use bevy::{prelude::*, app::AppExit};
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::controls::{MetaAction, Rebinding};
 
 // Components
 #[derive(Component)]
@@ -7,24 +10,28 @@ pub struct Player;
 // Systems
 pub fn meta_input (
     mut windows: ResMut<Windows>,
-    btn: Res<Input<MouseButton>>,
-    key: Res<Input<KeyCode>>,
+    rebinding: Res<Rebinding>,
 
-    mut ev_exit: EventWriter<AppExit>,
+    meta_query: Query<&ActionState<MetaAction>>,
 ) {
+    // The rebinding screen needs the cursor to itself.
+    if rebinding.open() {
+        return;
+    }
+
     let window = windows.get_primary_mut().unwrap();
+    let action_state = match meta_query.iter().next() {
+        Some(action_state) => action_state,
+        None => return,
+    };
 
-    if btn.just_pressed(MouseButton::Left) {
+    if action_state.just_pressed(MetaAction::LockCursor) {
         window.set_cursor_lock_mode(true);
         window.set_cursor_visibility(false);
     }
 
-    if key.just_pressed(KeyCode::Tab) {
+    if action_state.just_pressed(MetaAction::UnlockCursor) {
         window.set_cursor_lock_mode(false);
         window.set_cursor_visibility(true);
     }
-
-    if key.just_pressed(KeyCode::Escape) {
-        ev_exit.send(AppExit);
-    }
 }
\ No newline at end of file
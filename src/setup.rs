@@ -3,17 +3,20 @@ use heron::{prelude::*, PendingConvexCollision};
 use iyes_loopless::state::NextState;
 use leafwing_input_manager::prelude::*;
 
-use crate::{actions::Action, player::Player, map::{geometric::Rect3Room, MapScale}, GameState};
+use crate::{actions::Action, camera::{CameraRig, LookSettings}, controls::Controls, digging::DiggingTool, movement::{CharacterController, Crouch, Jump, MovementModifiers, Stamina}, player::Player, map::{geometric::Rect3Room, MapScale}, GameState};
 
 //use super::{GameState, TextureAssets};
 
 const PLAYER_HEIGHT: f32 = 0.4;
+const PLAYER_CROUCH_HEIGHT: f32 = 0.1;
 
 // Systems
 pub fn spawn_actors (
     mut commands: Commands,
 
     map_scale: Res<MapScale>,
+    controls: Res<Controls>,
+    look_settings: Res<LookSettings>,
 
     room_query: Query<&Rect3Room>,
 ) {
@@ -25,40 +28,46 @@ pub fn spawn_actors (
         spawn_pos = room.rect.center();
     }
     
-    spawn_pos *= **map_scale;
+    spawn_pos = map_scale.grid_to_world(spawn_pos);
 
     // Player
     commands
         .spawn_bundle(InputManagerBundle::<Action> {
             action_state: ActionState::default(),
-            input_map: InputMap::new([(Action::Jump, KeyCode::Space),
-                                      (Action::Crouch, KeyCode::LControl),
-                                      (Action::StrafeRight, KeyCode::D),
-                                      (Action::StrafeLeft, KeyCode::A),
-                                      (Action::WalkForward, KeyCode::W),
-                                      (Action::WalkBackward, KeyCode::S),
-                                     ])
+            input_map: controls.input_map(),
         })
         .insert(Player)
+        .insert(DiggingTool::default())
+        .insert(Jump::default())
+        .insert(Crouch::new(
+            PLAYER_HEIGHT / 2.0,
+            PLAYER_CROUCH_HEIGHT / 2.0,
+            PLAYER_HEIGHT - (PLAYER_HEIGHT / 4.0),
+            PLAYER_CROUCH_HEIGHT - (PLAYER_CROUCH_HEIGHT / 4.0),
+        ))
         .insert(Transform {
             translation: spawn_pos,
             ..default()
         })
         .insert(GlobalTransform::identity())
-        .insert(RigidBody::Dynamic)
-        .insert(Velocity::default())
-        .insert(RotationConstraints {
-            allow_x: false,
-            allow_y: false,
-            allow_z: false,
-        })
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(CharacterController::for_scale(&map_scale))
+        .insert(CameraRig::default())
+        .insert(Stamina::default())
+        .insert(MovementModifiers::default())
         .insert(CollisionShape::Capsule {
             radius: 0.2,
             half_segment: PLAYER_HEIGHT / 2.0,
         })
         // Camera
         .with_children(|c| {
-            c.spawn_bundle(PerspectiveCameraBundle::new_3d())
+            c.spawn_bundle(PerspectiveCameraBundle {
+                    perspective_projection: PerspectiveProjection {
+                        fov: look_settings.fov.to_radians(),
+                        ..default()
+                    },
+                    ..PerspectiveCameraBundle::new_3d()
+                })
                 .insert( Transform {
                     translation: Vec3::new(0.0, PLAYER_HEIGHT - (PLAYER_HEIGHT / 4.0), 0.0),
                     ..default()